@@ -127,6 +127,7 @@
                 ./Cargo.lock
                 ./build.rs
                 ./migrations
+                ./migrations-sqlite
                 crate
               ];
             };
@@ -201,7 +202,7 @@
               commonArgs
               // {
                 inherit cargoArtifacts;
-                cargoClippyExtraArgs = "--all-targets -- --deny warnings";
+                cargoClippyExtraArgs = "--all-targets --all-features -- --deny warnings";
               }
             );
 
@@ -216,6 +217,7 @@
               commonArgs
               // {
                 inherit cargoArtifacts;
+                cargoNextestExtraArgs = "--all-features";
                 partitions = 1;
                 partitionType = "count";
               }
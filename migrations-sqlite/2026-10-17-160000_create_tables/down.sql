@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE "backfill_progress";
+DROP TABLE "images";
+DROP TABLE "memes";
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+CREATE TABLE "memes"(
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"spoiler" BOOL NOT NULL,
+	"text" TEXT NOT NULL,
+	"timestamp" TIMESTAMP NOT NULL,
+	"account" TEXT NOT NULL,
+	"channel" TEXT NOT NULL,
+	"telegram_id" INTEGER,
+	"matrix_id" TEXT UNIQUE,
+	"telegram_chat_id" BIGINT,
+	"repost_of" INTEGER REFERENCES "memes"("id") ON DELETE SET NULL,
+	UNIQUE ("telegram_chat_id", "telegram_id")
+);
+CREATE INDEX "memes_repost_of_idx" ON "memes"("repost_of");
+
+CREATE TABLE "images"(
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"meme_id" INTEGER NOT NULL REFERENCES "memes"("id") ON DELETE CASCADE,
+	"position" INTEGER NOT NULL,
+	"filename" TEXT NOT NULL,
+	"telegram_id" INTEGER,
+	"matrix_id" TEXT,
+	"kind" TEXT NOT NULL DEFAULT 'photo',
+	"content_hash" TEXT,
+	"perceptual_hash" BIGINT,
+	UNIQUE ("meme_id", "position")
+);
+CREATE INDEX "images_content_hash_idx" ON "images"("content_hash");
+CREATE INDEX "images_filename_idx" ON "images"("filename");
+CREATE INDEX "images_telegram_id_idx" ON "images"("telegram_id");
+CREATE INDEX "images_matrix_id_idx" ON "images"("matrix_id");
+
+CREATE TABLE "backfill_progress"(
+	"chat_id" BIGINT NOT NULL PRIMARY KEY,
+	"message_id" INTEGER NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE "tags"(
+	"id" INTEGER NOT NULL PRIMARY KEY,
+	"name" TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE "meme_tags"(
+	"meme_id" INTEGER NOT NULL REFERENCES "memes"("id") ON DELETE CASCADE,
+	"tag_id" INTEGER NOT NULL REFERENCES "tags"("id") ON DELETE CASCADE,
+	PRIMARY KEY ("meme_id", "tag_id")
+);
+CREATE INDEX "meme_tags_tag_id_idx" ON "meme_tags"("tag_id");
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "images_ocr_pending_idx";
+ALTER TABLE "images" DROP COLUMN "ocr_text";
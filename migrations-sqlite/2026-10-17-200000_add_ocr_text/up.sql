@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "ocr_text" TEXT;
+CREATE INDEX "images_ocr_pending_idx" ON "images"("filename") WHERE "ocr_text" IS NULL;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "images" DROP COLUMN "size";
+ALTER TABLE "images" DROP COLUMN "height";
+ALTER TABLE "images" DROP COLUMN "width";
+ALTER TABLE "images" DROP COLUMN "mime_type";
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "mime_type" TEXT;
+ALTER TABLE "images" ADD COLUMN "width" INTEGER;
+ALTER TABLE "images" ADD COLUMN "height" INTEGER;
+ALTER TABLE "images" ADD COLUMN "size" BIGINT;
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "images_hash_band_3_idx";
+DROP INDEX IF EXISTS "images_hash_band_2_idx";
+DROP INDEX IF EXISTS "images_hash_band_1_idx";
+DROP INDEX IF EXISTS "images_hash_band_0_idx";
+ALTER TABLE "images" DROP COLUMN "hash_band_3";
+ALTER TABLE "images" DROP COLUMN "hash_band_2";
+ALTER TABLE "images" DROP COLUMN "hash_band_1";
+ALTER TABLE "images" DROP COLUMN "hash_band_0";
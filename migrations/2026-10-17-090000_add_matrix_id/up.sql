@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "memes" ADD COLUMN "matrix_id" TEXT UNIQUE;
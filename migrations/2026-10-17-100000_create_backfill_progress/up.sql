@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE "backfill_progress"(
+	"chat_id" BIGINT NOT NULL PRIMARY KEY,
+	"message_id" INTEGER NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "memes" DROP CONSTRAINT "memes_telegram_chat_id_telegram_id_key";
+ALTER TABLE "memes" ADD CONSTRAINT "memes_telegram_id_key" UNIQUE ("telegram_id");
+ALTER TABLE "memes" DROP COLUMN "telegram_chat_id";
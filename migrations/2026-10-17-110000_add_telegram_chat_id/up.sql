@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "memes" ADD COLUMN "telegram_chat_id" BIGINT;
+ALTER TABLE "memes" DROP CONSTRAINT "memes_telegram_id_key";
+ALTER TABLE "memes" ADD CONSTRAINT "memes_telegram_chat_id_telegram_id_key" UNIQUE ("telegram_chat_id", "telegram_id");
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "memes" ADD COLUMN "filename" TEXT NOT NULL DEFAULT '';
+
+UPDATE "memes" SET "filename" = "images"."filename"
+FROM "images" WHERE "images"."meme_id" = "memes"."id" AND "images"."position" = 0;
+
+ALTER TABLE "memes" ALTER COLUMN "filename" DROP DEFAULT;
+
+DROP TABLE IF EXISTS "images";
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE "images"(
+	"id" SERIAL NOT NULL PRIMARY KEY,
+	"meme_id" INTEGER NOT NULL REFERENCES "memes"("id") ON DELETE CASCADE,
+	"position" INTEGER NOT NULL,
+	"filename" TEXT NOT NULL,
+	"telegram_id" INTEGER,
+	"matrix_id" TEXT,
+	UNIQUE ("meme_id", "position")
+);
+CREATE INDEX "images_telegram_id_idx" ON "images"("telegram_id");
+CREATE INDEX "images_matrix_id_idx" ON "images"("matrix_id");
+
+INSERT INTO "images"("meme_id", "position", "filename", "telegram_id", "matrix_id")
+SELECT "id", 0, "filename", "telegram_id", "matrix_id" FROM "memes";
+
+ALTER TABLE "memes" DROP COLUMN "filename";
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "images_filename_idx";
+DROP INDEX IF EXISTS "images_content_hash_idx";
+ALTER TABLE "images" DROP COLUMN "content_hash";
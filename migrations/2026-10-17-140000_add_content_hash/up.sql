@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "content_hash" TEXT;
+CREATE INDEX "images_content_hash_idx" ON "images"("content_hash");
+CREATE INDEX "images_filename_idx" ON "images"("filename");
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "memes_repost_of_idx";
+ALTER TABLE "memes" DROP COLUMN "repost_of";
+ALTER TABLE "images" DROP COLUMN "perceptual_hash";
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "perceptual_hash" BIGINT;
+ALTER TABLE "memes" ADD COLUMN "repost_of" INTEGER REFERENCES "memes"("id") ON DELETE SET NULL;
+CREATE INDEX "memes_repost_of_idx" ON "memes"("repost_of");
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE "reactions"(
+	"image_id" INTEGER NOT NULL REFERENCES "images"("id") ON DELETE CASCADE,
+	"emoji" TEXT NOT NULL,
+	"count" INTEGER NOT NULL,
+	"updated_at" TIMESTAMP NOT NULL,
+	PRIMARY KEY ("image_id", "emoji")
+);
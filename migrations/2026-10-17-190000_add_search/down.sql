@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "memes_search_idx";
+ALTER TABLE "memes" DROP COLUMN "search";
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "memes" ADD COLUMN "search" TSVECTOR GENERATED ALWAYS AS (
+	to_tsvector('german', "text") || to_tsvector('english', "text")
+) STORED;
+CREATE INDEX "memes_search_idx" ON "memes" USING GIN ("search");
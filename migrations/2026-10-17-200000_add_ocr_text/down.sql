@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "images_ocr_pending_idx";
+DROP INDEX IF EXISTS "images_search_idx";
+ALTER TABLE "images" DROP COLUMN "search";
+ALTER TABLE "images" DROP COLUMN "ocr_text";
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "ocr_text" TEXT;
+ALTER TABLE "images" ADD COLUMN "search" TSVECTOR GENERATED ALWAYS AS (
+	to_tsvector('german', coalesce("ocr_text", '')) || to_tsvector('english', coalesce("ocr_text", ''))
+) STORED;
+CREATE INDEX "images_search_idx" ON "images" USING GIN ("search");
+CREATE INDEX "images_ocr_pending_idx" ON "images"("filename") WHERE "ocr_text" IS NULL;
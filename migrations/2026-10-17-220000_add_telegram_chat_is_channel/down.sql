@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "memes" DROP COLUMN "telegram_chat_is_channel";
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "memes" ADD COLUMN "telegram_chat_is_channel" BOOLEAN;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE "images" ADD COLUMN "hash_band_0" INTEGER;
+ALTER TABLE "images" ADD COLUMN "hash_band_1" INTEGER;
+ALTER TABLE "images" ADD COLUMN "hash_band_2" INTEGER;
+ALTER TABLE "images" ADD COLUMN "hash_band_3" INTEGER;
+UPDATE "images" SET
+  "hash_band_0" = CAST(("perceptual_hash" >> 48) & 65535 AS INTEGER),
+  "hash_band_1" = CAST(("perceptual_hash" >> 32) & 65535 AS INTEGER),
+  "hash_band_2" = CAST(("perceptual_hash" >> 16) & 65535 AS INTEGER),
+  "hash_band_3" = CAST("perceptual_hash" & 65535 AS INTEGER)
+WHERE "perceptual_hash" IS NOT NULL;
+CREATE INDEX "images_hash_band_0_idx" ON "images"("hash_band_0");
+CREATE INDEX "images_hash_band_1_idx" ON "images"("hash_band_1");
+CREATE INDEX "images_hash_band_2_idx" ON "images"("hash_band_2");
+CREATE INDEX "images_hash_band_3_idx" ON "images"("hash_band_3");
@@ -12,6 +12,7 @@
 
 let
   inherit (lib)
+    filterAttrsRecursive
     mkEnableOption
     mkIf
     mkOption
@@ -40,6 +41,32 @@ in
             type = types.path;
           };
 
+          sessionFile = mkOption {
+            description = "File to keep the Telegram session and the known groups in, so that restarts don't log in again";
+            default = "/var/lib/kommemeorate/telegram.session";
+            type = types.nullOr types.path;
+          };
+
+          catchUp = mkOption {
+            description = "Whether to collect the memes posted while kommemeorate was not running. Requires `sessionFile`";
+            default = false;
+            type = types.bool;
+          };
+
+          media = mkOption {
+            description = "kinds of media to collect";
+            default = [ "photo" ];
+            type = types.listOf (
+              types.enum [
+                "photo"
+                "video"
+                "animation"
+                "sticker"
+                "document"
+              ]
+            );
+          };
+
           groups = mkOption {
             description = "groups to collect memes from";
             type = types.listOf (
@@ -81,18 +108,26 @@ in
             type = types.path;
           };
 
+          sessionFile = mkOption {
+            description = "File to keep the Matrix session in, so that restarts don't log in again. The client state is kept next to it";
+            default = "/var/lib/kommemeorate/matrix.session";
+            type = types.nullOr types.path;
+          };
+
           rooms = mkOption {
             description = "Rooms to collect memes from";
             type = types.listOf (
               types.submodule {
-                name = mkOption {
-                  description = "name to identify Room";
-                  type = types.str;
-                };
+                options = {
+                  name = mkOption {
+                    description = "name to identify Room";
+                    type = types.str;
+                  };
 
-                address = mkOption {
-                  description = "Matrix room address";
-                  type = types.str;
+                  address = mkOption {
+                    description = "Matrix room address";
+                    type = types.str;
+                  };
                 };
               }
             );
@@ -105,7 +140,7 @@ in
       type = types.submodule {
         options = {
           url = mkOption {
-            description = "PostgreSQL database connection URL";
+            description = "database connection URL, either `postgres://…` or, when built with the `sqlite` feature, `sqlite://` followed by the path of the database file";
             type = types.str;
           };
         };
@@ -119,10 +154,147 @@ in
             description = "where to store the memes";
             type = types.path;
           };
+
+          backend = mkOption {
+            description = "where to store the meme files, `path` is only used for scratch data with `s3`";
+            default = "local";
+            type = types.enum [
+              "local"
+              "s3"
+            ];
+          };
+
+          s3 = mkOption {
+            description = "S3 bucket to store the meme files in";
+            default = null;
+            type = types.nullOr (
+              types.submodule {
+                options = {
+                  endpoint = mkOption {
+                    description = "endpoint of a self-hosted store, uses AWS if unset";
+                    default = null;
+                    type = types.nullOr types.str;
+                  };
+
+                  bucket = mkOption {
+                    description = "S3 bucket";
+                    type = types.str;
+                  };
+
+                  region = mkOption {
+                    description = "S3 region";
+                    default = "us-east-1";
+                    type = types.str;
+                  };
+
+                  accessKeyIdFile = mkOption {
+                    description = "File containing the S3 access key id";
+                    type = types.path;
+                  };
+
+                  secretAccessKeyFile = mkOption {
+                    description = "File containing the S3 secret access key";
+                    type = types.path;
+                  };
+
+                  allowHttp = mkOption {
+                    description = "Whether to allow unencrypted connections to the endpoint";
+                    default = false;
+                    type = types.bool;
+                  };
+                };
+              }
+            );
+          };
+
+          spool = mkOption {
+            description = "Whether to spool incoming memes to disk until they are stored";
+            default = false;
+            type = types.bool;
+          };
         };
       };
     };
 
+    processing = mkOption {
+      default = { };
+      type = types.submodule {
+        options = {
+          repostThreshold = mkOption {
+            description = "maximum Hamming distance between perceptual hashes for a meme to be considered a repost, disables repost detection if unset";
+            default = null;
+            type = types.nullOr types.ints.unsigned;
+          };
+
+          ocr = mkOption {
+            description = "recognising text in memes, disabled if unset";
+            default = null;
+            type = types.nullOr (
+              types.submodule {
+                options = {
+                  command = mkOption {
+                    description = "OCR engine, called as `command stdin stdout -l languages`";
+                    default = lib.getExe pkgs.tesseract;
+                    defaultText = lib.literalExpression "lib.getExe pkgs.tesseract";
+                    type = types.path;
+                  };
+
+                  languages = mkOption {
+                    description = "languages to recognise, joined by `+`";
+                    default = "deu+eng";
+                    type = types.str;
+                  };
+                };
+              }
+            );
+          };
+
+          thumbnails = mkOption {
+            description = "scaled-down copies of stored memes, disabled if unset";
+            default = null;
+            type = types.nullOr (
+              types.submodule {
+                options = {
+                  sizes = mkOption {
+                    description = "the longest side of each thumbnail, in pixels";
+                    default = [
+                      320
+                      960
+                    ];
+                    type = types.listOf types.ints.positive;
+                  };
+
+                  format = mkOption {
+                    description = "thumbnail format";
+                    default = "jpeg";
+                    type = types.enum [
+                      "jpeg"
+                      "webp"
+                    ];
+                  };
+                };
+              }
+            );
+          };
+        };
+      };
+    };
+
+    http = mkOption {
+      description = "serving the collected memes, disabled if unset";
+      default = null;
+      type = types.nullOr (
+        types.submodule {
+          options = {
+            bind = mkOption {
+              description = "where to listen, e.g., `127.0.0.1:8080`";
+              type = types.str;
+            };
+          };
+        }
+      );
+    };
+
     user = mkOption {
       description = "user to run as";
       type = types.str;
@@ -139,14 +311,19 @@ in
       cfg = config.die-koma.kommemeorate;
 
       configFile = pkgs.writeText "kommemeorate-config.toml" (
-        std.serde.toTOML {
-          inherit (cfg)
-            storage
-            database
-            telegram
-            matrix
-            ;
-        }
+        std.serde.toTOML (
+          # TOML has no null, so unset options are left out
+          filterAttrsRecursive (_: value: value != null) {
+            inherit (cfg)
+              storage
+              processing
+              database
+              telegram
+              matrix
+              http
+              ;
+          }
+        )
       );
     in
     mkIf cfg.enable {
@@ -162,6 +339,9 @@ in
             User = cfg.user;
             Group = cfg.group;
             ExecStart = "${lib.getExe pkgs.kommemeorate} --config ${configFile}";
+            # for the session files
+            StateDirectory = "kommemeorate";
+            StateDirectoryMode = "0700";
             Type = "simple";
           };
           wantedBy = [ "multi-user.target" ];
@@ -3,7 +3,8 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
-use clap::Parser;
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -11,4 +12,84 @@ use std::path::PathBuf;
 pub(crate) struct Cli {
     #[arg(long)]
     pub(crate) config: PathBuf,
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Collect memes from the message history of a Telegram group
+    Backfill {
+        /// id of the (configured) Telegram group to collect memes from
+        #[arg(long)]
+        telegram_group: i64,
+        /// ignore messages sent before this date
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// stop at the first message sent after this date
+        #[arg(long)]
+        until: Option<NaiveDate>,
+    },
+    /// Check that the stored files and the database agree
+    ///
+    /// Reports files that no meme refers to, as well as memes whose
+    /// files are missing, empty, or don't match their content hash.
+    /// Should not run while memes are being collected.
+    Fsck {
+        /// quarantine orphaned and corrupt files and remove broken memes
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Retry storing memes that failed repeatedly before
+    Replay,
+    /// Recognise text in stored images that don't have any yet
+    ///
+    /// Requires OCR to be configured. Runs in the background while
+    /// memes are being collected as well.
+    ReindexOcr {
+        /// recognise the text of all images again, e.g., after
+        /// changing the languages
+        #[arg(long)]
+        all: bool,
+    },
+    /// Generate thumbnails of stored images that are missing some
+    ///
+    /// Requires thumbnails to be configured. New memes get their
+    /// thumbnails while they are being collected.
+    RegenThumbnails {
+        /// generate all thumbnails again, e.g., after changing the
+        /// format
+        #[arg(long)]
+        all: bool,
+    },
+    /// Search the captions of stored memes
+    ///
+    /// Supports `"quoted phrases"`, `or`, and `-excluded` words.
+    /// Captions are matched in German and English, so that inflected
+    /// forms are found as well.
+    Search {
+        /// what to search for; without any words, list the newest memes
+        query: Vec<String>,
+        /// only memes from this channel
+        #[arg(long)]
+        channel: Option<String>,
+        /// only memes posted by this account
+        #[arg(long)]
+        account: Option<String>,
+        /// only memes posted on or after this date
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// only memes posted on or before this date
+        #[arg(long)]
+        until: Option<NaiveDate>,
+        /// only memes marked as spoilers
+        #[arg(long, conflicts_with = "no_spoiler")]
+        spoiler: bool,
+        /// only memes not marked as spoilers
+        #[arg(long)]
+        no_spoiler: bool,
+        /// show at most this many memes
+        #[arg(long, default_value_t = 20)]
+        limit: i64,
+    },
 }
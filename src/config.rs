@@ -3,6 +3,7 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::{fmt::Debug, fs::read_to_string};
 
@@ -10,12 +11,17 @@ use anyhow::{Context, Error, Result};
 use config::{Config, Environment, FileFormat};
 use serde::Deserialize;
 
+use crate::consumer::MediaKind;
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct Configuration {
     telegram: TelegramConfiguration,
     matrix: MatrixConfiguration,
     storage: StorageConfiguration,
+    #[serde(default)]
+    processing: ProcessingConfiguration,
     database: DatabaseConfiguration,
+    http: Option<HttpConfiguration>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,9 +30,18 @@ struct TelegramConfiguration {
     api_id_file: PathBuf,
     api_hash_file: PathBuf,
     password_file: PathBuf,
+    session_file: Option<PathBuf>,
+    #[serde(default)]
+    catch_up: bool,
+    #[serde(default = "default_media")]
+    media: Vec<MediaKind>,
     groups: Vec<Group>,
 }
 
+fn default_media() -> Vec<MediaKind> {
+    vec![MediaKind::Photo]
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct Group {
     pub(crate) id: i64,
@@ -38,6 +53,9 @@ pub(crate) struct Telegram {
     api_id: i32,
     api_hash: String,
     bot_password: String,
+    session_file: Option<PathBuf>,
+    catch_up: bool,
+    media: Vec<MediaKind>,
     groups: Vec<Group>,
 }
 
@@ -50,6 +68,20 @@ impl Telegram {
         &self.bot_password
     }
 
+    pub(crate) fn session_file(&self) -> Option<&Path> {
+        self.session_file.as_deref()
+    }
+
+    /// Catching up only makes sense if there is a persisted update
+    /// state to catch up from.
+    pub(crate) fn catch_up(&self) -> bool {
+        self.catch_up && self.session_file.is_some()
+    }
+
+    pub(crate) fn collects(&self, kind: MediaKind) -> bool {
+        self.media.contains(&kind)
+    }
+
     pub(crate) fn groups(&self) -> impl Iterator<Item = &Group> {
         self.groups.iter()
     }
@@ -61,6 +93,9 @@ impl Debug for Telegram {
             .field("api_id", &"[REDACTED]")
             .field("api_hash", &"[REDACTED]")
             .field("bot_password", &"[REDACTED]")
+            .field("session_file", &self.session_file)
+            .field("catch_up", &self.catch_up)
+            .field("media", &self.media)
             .field("groups", &self.groups)
             .finish()
     }
@@ -78,6 +113,9 @@ impl TryFrom<&TelegramConfiguration> for Telegram {
             api_id,
             api_hash,
             bot_password,
+            session_file: value.session_file.clone(),
+            catch_up: value.catch_up,
+            media: value.media.clone(),
             groups: value.groups.clone(),
         })
     }
@@ -89,6 +127,7 @@ struct MatrixConfiguration {
     homeserver: String,
     username: String,
     password_file: PathBuf,
+    session_file: Option<PathBuf>,
     rooms: Vec<Room>,
 }
 
@@ -99,14 +138,15 @@ pub(crate) struct Room {
     pub(crate) address: String,
 }
 
+#[derive(Clone)]
 pub(crate) struct Matrix {
     homeserver: String,
     username: String,
     password: String,
+    session_file: Option<PathBuf>,
     rooms: Vec<Room>,
 }
 
-#[allow(unused)]
 impl Matrix {
     pub(crate) fn homeserver(&self) -> &str {
         &self.homeserver
@@ -120,6 +160,12 @@ impl Matrix {
         &self.password
     }
 
+    /// Where to keep the session, so that restarts don't log in
+    /// again. The client state lives next to it.
+    pub(crate) fn session_file(&self) -> Option<&Path> {
+        self.session_file.as_deref()
+    }
+
     pub(crate) fn rooms(&self) -> impl Iterator<Item = &Room> {
         self.rooms.iter()
     }
@@ -131,6 +177,7 @@ impl Debug for Matrix {
             .field("homeserver", &self.homeserver)
             .field("username", &self.username)
             .field("password", &"[REDACTED]")
+            .field("session_file", &self.session_file)
             .field("rooms", &self.rooms)
             .finish()
     }
@@ -145,6 +192,7 @@ impl TryFrom<&MatrixConfiguration> for Matrix {
         Ok(Self {
             homeserver: value.homeserver.clone(),
             username: value.username.clone(),
+            session_file: value.session_file.clone(),
             rooms: value.rooms.clone(),
             password,
         })
@@ -155,12 +203,229 @@ impl TryFrom<&MatrixConfiguration> for Matrix {
 #[serde(rename_all = "camelCase")]
 pub(crate) struct StorageConfiguration {
     path: PathBuf,
+    #[serde(default)]
+    backend: StorageBackend,
+    s3: Option<S3Configuration>,
+    #[serde(default)]
+    spool: bool,
+}
+
+/// What to do with memes besides storing them. Everything is off
+/// unless configured.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ProcessingConfiguration {
+    repost_threshold: Option<u32>,
+    ocr: Option<OcrConfiguration>,
+    thumbnails: Option<ThumbnailConfiguration>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum StorageBackend {
+    #[default]
+    Local,
+    S3,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct S3Configuration {
+    endpoint: Option<String>,
+    bucket: String,
+    #[serde(default = "default_region")]
+    region: String,
+    access_key_id_file: PathBuf,
+    secret_access_key_file: PathBuf,
+    #[serde(default)]
+    allow_http: bool,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Recognising text in images, with Tesseract or a compatible engine.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OcrConfiguration {
+    #[serde(default = "default_ocr_command")]
+    command: PathBuf,
+    #[serde(default = "default_ocr_languages")]
+    languages: String,
+}
+
+fn default_ocr_command() -> PathBuf {
+    "tesseract".into()
+}
+
+fn default_ocr_languages() -> String {
+    "deu+eng".to_string()
+}
+
+impl OcrConfiguration {
+    /// The engine, called as `command stdin stdout -l languages`.
+    pub(crate) fn command(&self) -> &Path {
+        &self.command
+    }
+
+    /// The languages to recognise, joined by `+`.
+    pub(crate) fn languages(&self) -> &str {
+        &self.languages
+    }
+}
+
+/// Scaled-down copies of stored images, e.g., for the gallery.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ThumbnailConfiguration {
+    #[serde(default = "default_thumbnail_sizes")]
+    sizes: Vec<u32>,
+    #[serde(default)]
+    format: ThumbnailFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum ThumbnailFormat {
+    /// lossless, so usually larger than JPEG
+    Webp,
+    #[default]
+    Jpeg,
+}
+
+fn default_thumbnail_sizes() -> Vec<u32> {
+    vec![320, 960]
+}
+
+impl ThumbnailConfiguration {
+    /// The longest side of each thumbnail, in pixels.
+    pub(crate) fn sizes(&self) -> &[u32] {
+        &self.sizes
+    }
+
+    pub(crate) fn format(&self) -> ThumbnailFormat {
+        self.format
+    }
+}
+
+pub(crate) struct S3 {
+    endpoint: Option<String>,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+    allow_http: bool,
+}
+
+impl S3 {
+    /// The endpoint of a self-hosted store, e.g. MinIO. Uses AWS if unset.
+    pub(crate) fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    pub(crate) fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub(crate) fn region(&self) -> &str {
+        &self.region
+    }
+
+    pub(crate) fn credentials(&self) -> (&str, &str) {
+        (&self.access_key_id, &self.secret_access_key)
+    }
+
+    pub(crate) fn allow_http(&self) -> bool {
+        self.allow_http
+    }
+}
+
+impl Debug for S3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"[REDACTED]")
+            .field("allow_http", &self.allow_http)
+            .finish()
+    }
+}
+
+impl TryFrom<&S3Configuration> for S3 {
+    type Error = Error;
+
+    fn try_from(value: &S3Configuration) -> std::result::Result<Self, Self::Error> {
+        let access_key_id = read_to_string(value.access_key_id_file.clone())?
+            .trim()
+            .to_string();
+        let secret_access_key = read_to_string(value.secret_access_key_file.clone())?
+            .trim()
+            .to_string();
+
+        Ok(Self {
+            endpoint: value.endpoint.clone(),
+            bucket: value.bucket.clone(),
+            region: value.region.clone(),
+            access_key_id,
+            secret_access_key,
+            allow_http: value.allow_http,
+        })
+    }
 }
 
 impl StorageConfiguration {
+    /// The local directory holding meme files with the local backend.
+    /// Other backends only use it for scratch data.
     pub(crate) fn path(&self) -> &Path {
         &self.path
     }
+
+    pub(crate) fn backend(&self) -> StorageBackend {
+        self.backend
+    }
+
+    pub(crate) fn s3(&self) -> Result<S3> {
+        self.s3
+            .as_ref()
+            .context("the s3 storage backend requires a [storage.s3] section")?
+            .try_into()
+    }
+
+    /// The directory to spool incoming events in, if enabled. Only
+    /// takes effect on restart.
+    pub(crate) fn spool(&self) -> Option<PathBuf> {
+        self.spool.then(|| self.path.join(".spool"))
+    }
+
+    /// The same storage without a spool, for one-off commands that
+    /// must not pick up the events spooled by a running daemon.
+    pub(crate) fn without_spool(&self) -> Self {
+        Self {
+            spool: false,
+            ..self.clone()
+        }
+    }
+}
+
+impl ProcessingConfiguration {
+    /// Maximum Hamming distance between perceptual hashes for a meme
+    /// to be considered a repost, if repost detection is enabled.
+    pub(crate) fn repost_threshold(&self) -> Option<u32> {
+        self.repost_threshold
+    }
+
+    /// How to recognise text in images, if enabled.
+    pub(crate) fn ocr(&self) -> Option<&OcrConfiguration> {
+        self.ocr.as_ref()
+    }
+
+    /// Which thumbnails to generate, if any.
+    pub(crate) fn thumbnails(&self) -> Option<&ThumbnailConfiguration> {
+        self.thumbnails.as_ref()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -170,11 +435,28 @@ pub(crate) struct DatabaseConfiguration {
 }
 
 impl DatabaseConfiguration {
+    /// Where the database lives: a `postgres://` URL, or `sqlite://`
+    /// followed by the path of the database file if built with the
+    /// `sqlite` feature.
     pub(crate) fn url(&self) -> &str {
         &self.url
     }
 }
 
+/// The read-only gallery and JSON API.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HttpConfiguration {
+    bind: SocketAddr,
+}
+
+impl HttpConfiguration {
+    /// Where to listen, e.g., `127.0.0.1:8080`.
+    pub(crate) fn bind(&self) -> SocketAddr {
+        self.bind
+    }
+}
+
 impl Configuration {
     pub(crate) fn load(config_file: PathBuf) -> Result<Self> {
         let settings = Config::builder()
@@ -196,7 +478,6 @@ impl Configuration {
         (&self.telegram).try_into()
     }
 
-    #[allow(unused)]
     pub(crate) fn matrix(&self) -> Result<Matrix> {
         (&self.matrix).try_into()
     }
@@ -208,13 +489,22 @@ impl Configuration {
     pub(crate) fn storage(&self) -> &StorageConfiguration {
         &self.storage
     }
+
+    pub(crate) fn processing(&self) -> &ProcessingConfiguration {
+        &self.processing
+    }
+
+    /// How to serve the archive, if at all.
+    pub(crate) fn http(&self) -> Option<&HttpConfiguration> {
+        self.http.as_ref()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use test_log::test;
 
-    use crate::config::{Matrix, Telegram};
+    use crate::config::{Matrix, S3, Telegram};
 
     const NEEDLE: &str = "0x23acab";
     const REDACTED: &str = "[REDACTED]";
@@ -225,6 +515,9 @@ mod test {
             api_id: 0,
             api_hash: String::new(),
             bot_password: NEEDLE.to_string(),
+            session_file: None,
+            catch_up: false,
+            media: vec![],
             groups: vec![],
         };
 
@@ -238,10 +531,26 @@ mod test {
             homeserver: String::new(),
             username: String::new(),
             password: NEEDLE.to_string(),
+            session_file: None,
             rooms: vec![],
         };
 
         assert!(format!("{matrix:?}").contains(REDACTED));
         assert!(!format!("{matrix:?}").contains(NEEDLE));
     }
+
+    #[test]
+    fn s3_debug() {
+        let s3 = S3 {
+            endpoint: None,
+            bucket: String::new(),
+            region: String::new(),
+            access_key_id: String::new(),
+            secret_access_key: NEEDLE.to_string(),
+            allow_http: false,
+        };
+
+        assert!(format!("{s3:?}").contains(REDACTED));
+        assert!(!format!("{s3:?}").contains(NEEDLE));
+    }
 }
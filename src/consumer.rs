@@ -3,56 +3,188 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
+mod archive;
+mod backfill;
 mod db;
+mod dead_letters;
+mod fsck;
+mod journal;
+mod media;
+mod ocr;
+mod reactions;
+mod reposts;
+mod search;
+mod tags;
+mod thumbnails;
 
 use std::{
-    fmt::Debug,
-    path::{Path, PathBuf},
+    fmt::{Debug, Display},
+    sync::Arc,
+    time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use chrono::NaiveDateTime;
 use diesel::{
-    PgConnection,
+    Connection,
     dsl::{delete, insert_into, update},
 };
+use futures::{StreamExt, stream::FuturesOrdered};
 use grammers_client::types::Chat;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::{
-    fs, select,
-    sync::mpsc::{self, Receiver, Sender},
-    task::JoinHandle,
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        watch,
+    },
+    task::{JoinHandle, spawn_blocking},
+    time::sleep,
 };
 
-use crate::config::{DatabaseConfiguration, StorageConfiguration};
+use crate::{
+    config::{DatabaseConfiguration, ProcessingConfiguration, StorageConfiguration},
+    storage::{Backend, Storage},
+};
+use db::{AnyConnection, Pool, PooledConnection, models::Image};
+use dead_letters::DeadLetters;
+use journal::Journal;
+use media::{Media, Rejected};
+use thumbnails::Thumbnails;
 
-#[derive(Debug)]
+pub(crate) use archive::{Archive, Entry, RankedEntry, TagCount};
+pub(crate) use backfill::BackfillProgress;
+pub(crate) use dead_letters::replay;
+pub(crate) use fsck::fsck;
+pub(crate) use ocr::reindex_ocr;
+pub(crate) use search::{Query, search};
+pub(crate) use thumbnails::regen_thumbnails;
+
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) enum Source {
     Telegram {
         account: Option<String>,
         channel: Option<String>,
+        chat: Option<i64>,
+        /// whether `chat` is a channel or supergroup, whose message
+        /// ids are only unique within the chat
+        #[serde(default)]
+        is_channel: bool,
         id: i32,
     },
-    #[allow(unused)]
-    Matrix { account: String, channel: String },
+    Matrix {
+        account: Option<String>,
+        channel: Option<String>,
+        id: String,
+    },
 }
 
 impl Source {
-    pub(crate) fn telegram(chat: Option<Chat>, channel: Option<&str>, id: i32) -> Self {
+    pub(crate) fn telegram(
+        sender: Option<Chat>,
+        channel: Option<&str>,
+        chat: Option<i64>,
+        is_channel: bool,
+        id: i32,
+    ) -> Self {
         Self::Telegram {
-            account: match chat {
+            account: match sender {
                 Some(Chat::User(user)) => user.username().map(|name| name.to_string()),
                 Some(Chat::Group(group)) => group.title().map(|name| name.to_string()),
                 Some(Chat::Channel(channel)) => Some(channel.title().to_string()),
                 None => None,
             },
             channel: channel.map(|name| name.to_string()),
+            chat,
+            is_channel,
             id,
         }
     }
+
+    pub(crate) fn matrix(account: Option<&str>, channel: Option<&str>, id: &str) -> Self {
+        Self::Matrix {
+            account: account.map(|name| name.to_string()),
+            channel: channel.map(|name| name.to_string()),
+            id: id.to_string(),
+        }
+    }
+
+    fn account(&self) -> &str {
+        match self {
+            Self::Telegram { account, .. } | Self::Matrix { account, .. } => {
+                account.as_deref().unwrap_or_default()
+            }
+        }
+    }
+
+    fn channel(&self) -> &str {
+        match self {
+            Self::Telegram { channel, .. } | Self::Matrix { channel, .. } => {
+                channel.as_deref().unwrap_or_default()
+            }
+        }
+    }
+
+    fn telegram_id(&self) -> Option<i32> {
+        match self {
+            Self::Telegram { id, .. } => Some(*id),
+            Self::Matrix { .. } => None,
+        }
+    }
+
+    fn telegram_chat_id(&self) -> Option<i64> {
+        match self {
+            Self::Telegram { chat, .. } => *chat,
+            Self::Matrix { .. } => None,
+        }
+    }
+
+    fn telegram_chat_is_channel(&self) -> Option<bool> {
+        match self {
+            Self::Telegram { is_channel, .. } => Some(*is_channel),
+            Self::Matrix { .. } => None,
+        }
+    }
+
+    fn matrix_id(&self) -> Option<&str> {
+        match self {
+            Self::Telegram { .. } => None,
+            Self::Matrix { id, .. } => Some(id),
+        }
+    }
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MediaKind {
+    Photo,
+    Video,
+    Animation,
+    Sticker,
+    Document,
+}
+
+impl MediaKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Photo => "photo",
+            Self::Video => "video",
+            Self::Animation => "animation",
+            Self::Sticker => "sticker",
+            Self::Document => "document",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub(crate) struct MemeImage {
+    /// not serialised, since it is usually better kept in a file of its own
+    #[serde(skip)]
     data: Vec<u8>,
+    kind: MediaKind,
+    mime_type: String,
     spoiler: bool,
     text: String,
     timestamp: NaiveDateTime,
@@ -62,6 +194,8 @@ impl Debug for MemeImage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MemeImage")
             .field("data", &"[elided]")
+            .field("kind", &self.kind)
+            .field("mime_type", &self.mime_type)
             .field("spoiler", &self.spoiler)
             .field("text", &self.text)
             .field("timestamp", &self.timestamp)
@@ -72,12 +206,16 @@ impl Debug for MemeImage {
 impl MemeImage {
     pub(crate) fn new(
         data: Vec<u8>,
+        kind: MediaKind,
+        mime_type: String,
         spoiler: bool,
         text: String,
         timestamp: NaiveDateTime,
     ) -> Self {
         Self {
             data,
+            kind,
+            mime_type,
             spoiler,
             text,
             timestamp,
@@ -85,16 +223,57 @@ impl MemeImage {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) enum MemeEvent {
-    New { image: MemeImage, source: Source },
-    Updated { image: MemeImage, source: Source },
-    Deleted { source: Source },
+    /// A new meme, consisting of one or more images, each with its
+    /// own source. The first source identifies the meme as a whole.
+    New {
+        images: Vec<(MemeImage, Source)>,
+    },
+    Updated {
+        image: MemeImage,
+        source: Source,
+    },
+    Deleted {
+        source: Source,
+    },
+    /// The current reactions to a message, replacing earlier ones.
+    Reacted {
+        source: Source,
+        reactions: Vec<Reaction>,
+        timestamp: NaiveDateTime,
+    },
+    /// Someone changed their reactions to a message, changing the
+    /// earlier counts by `changes`.
+    ReactionsChanged {
+        source: Source,
+        changes: Vec<Reaction>,
+        timestamp: NaiveDateTime,
+    },
+}
+
+/// How often a message received a reaction with some emoji.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub(crate) struct Reaction {
+    emoji: String,
+    count: i32,
+}
+
+impl Reaction {
+    pub(crate) fn new(emoji: String, count: i32) -> Self {
+        Self { emoji, count }
+    }
 }
 
 impl MemeEvent {
     pub(crate) fn new(image: MemeImage, source: Source) -> Self {
-        Self::New { image, source }
+        Self::New {
+            images: vec![(image, source)],
+        }
+    }
+
+    pub(crate) fn album(images: Vec<(MemeImage, Source)>) -> Self {
+        Self::New { images }
     }
 
     pub(crate) fn edit(image: MemeImage, source: Source) -> Self {
@@ -104,6 +283,50 @@ impl MemeEvent {
     pub(crate) fn delete(source: Source) -> Self {
         Self::Deleted { source }
     }
+
+    pub(crate) fn react(
+        source: Source,
+        reactions: Vec<Reaction>,
+        timestamp: NaiveDateTime,
+    ) -> Self {
+        Self::Reacted {
+            source,
+            reactions,
+            timestamp,
+        }
+    }
+
+    pub(crate) fn change_reactions(
+        source: Source,
+        changes: Vec<Reaction>,
+        timestamp: NaiveDateTime,
+    ) -> Self {
+        Self::ReactionsChanged {
+            source,
+            changes,
+            timestamp,
+        }
+    }
+
+    fn images(&self) -> Vec<&MemeImage> {
+        match self {
+            Self::New { images } => images.iter().map(|(image, _)| image).collect(),
+            Self::Updated { image, .. } => vec![image],
+            Self::Deleted { .. } | Self::Reacted { .. } | Self::ReactionsChanged { .. } => {
+                Vec::new()
+            }
+        }
+    }
+
+    fn images_mut(&mut self) -> Vec<&mut MemeImage> {
+        match self {
+            Self::New { images } => images.iter_mut().map(|(image, _)| image).collect(),
+            Self::Updated { image, .. } => vec![image],
+            Self::Deleted { .. } | Self::Reacted { .. } | Self::ReactionsChanged { .. } => {
+                Vec::new()
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -111,62 +334,131 @@ pub(crate) enum Command {
     Shutdown,
 }
 
-type TaskResult = Result<(Receiver<Command>, Receiver<MemeEvent>)>;
+/// An event written to the spool, identified by its id.
+type Spooled = (Arc<Journal>, String);
+
+#[derive(Debug)]
+struct Queued {
+    event: MemeEvent,
+    /// where to acknowledge the event once it has been handled
+    spooled: Option<Spooled>,
+}
+
+/// Hands events to the consumer. With the spool enabled, events are
+/// written to disk before they are queued, and only removed once they
+/// have been handled, so that they survive restarts and outages.
+#[derive(Clone, Debug)]
+pub(crate) struct MemeSender {
+    spool: Option<Arc<Journal>>,
+    sender: Sender<Queued>,
+}
+
+impl MemeSender {
+    pub(crate) async fn send(&self, event: MemeEvent) -> Result<()> {
+        let spooled = match self.spool {
+            Some(ref spool) => Some((spool.clone(), spool.append(&event, &event).await?)),
+            None => None,
+        };
+
+        self.sender
+            .send(Queued { event, spooled })
+            .await
+            .map_err(|_| anyhow!("consumer has stopped"))
+    }
+}
+
+/// Hands the channels and any spooled events that are still pending
+/// over to whoever takes over.
+type TaskResult = Result<(Receiver<Command>, Receiver<Queued>, Vec<Spooled>)>;
 
 #[derive(Debug)]
 pub(crate) struct Consumer {
     task: JoinHandle<TaskResult>,
     control: Sender<Command>,
+    stopping: watch::Sender<bool>,
 }
 
 impl Consumer {
-    pub(crate) fn new(
+    pub(crate) async fn new(
         storage: StorageConfiguration,
+        processing: ProcessingConfiguration,
         database: DatabaseConfiguration,
-    ) -> Result<(Self, Sender<MemeEvent>)> {
+    ) -> Result<(Self, MemeSender)> {
         let (control, rx) = mpsc::channel(8);
         let (tx, consumer) = mpsc::channel(32);
 
+        // look for leftovers before anyone can add new events
+        let spool = storage.spool().map(|path| Arc::new(Journal::new(&path)));
+        let backlog = match spool {
+            Some(ref spool) => spool
+                .ids()
+                .await?
+                .into_iter()
+                .map(|id| (spool.clone(), id))
+                .collect(),
+            None => Vec::new(),
+        };
+
         Ok((
-            Self::with_control_and_consumer(storage, database, control, rx, consumer)?,
-            tx,
+            Self::with_control_and_consumer(
+                storage, processing, database, control, rx, consumer, backlog,
+            )?,
+            MemeSender { spool, sender: tx },
         ))
     }
 
     fn with_control_and_consumer(
         storage: StorageConfiguration,
+        processing: ProcessingConfiguration,
         database: DatabaseConfiguration,
         control: Sender<Command>,
         rx: Receiver<Command>,
-        consumer: Receiver<MemeEvent>,
+        consumer: Receiver<Queued>,
+        backlog: Vec<Spooled>,
     ) -> Result<Self> {
-        let task = tokio::spawn(async move {
-            let result = process(storage, database, rx, consumer).await;
-            if let Err(ref err) = result {
-                log::error!("{err}");
+        let stopping = watch::Sender::new(false);
+        let task = tokio::spawn({
+            let stopping = Stopping(stopping.subscribe());
+            async move {
+                let result = process(
+                    storage, processing, database, rx, consumer, backlog, stopping,
+                )
+                .await;
+                if let Err(ref err) = result {
+                    log::error!("{err}");
+                }
+                result
             }
-            result
         });
 
-        Ok(Self { task, control })
+        Ok(Self {
+            task,
+            control,
+            stopping,
+        })
     }
 
     pub(crate) async fn reload(
         self,
         storage: StorageConfiguration,
+        processing: ProcessingConfiguration,
         database: DatabaseConfiguration,
     ) -> Result<Self> {
         log::info!("restarting storage");
         let control = self.control.clone();
+        self.stopping.send_replace(true);
         if !self.control.is_closed() {
             self.control.send(Command::Shutdown).await?;
         }
-        let (rx, consumer) = self.task.await??;
-        Self::with_control_and_consumer(storage, database, control, rx, consumer)
+        let (rx, consumer, backlog) = self.task.await??;
+        Self::with_control_and_consumer(
+            storage, processing, database, control, rx, consumer, backlog,
+        )
     }
 
     pub(crate) async fn shutdown(self) -> Result<()> {
         log::info!("shutting down storage");
+        self.stopping.send_replace(true);
         if !self.control.is_closed() {
             self.control.send(Command::Shutdown).await?;
         }
@@ -175,169 +467,1225 @@ impl Consumer {
     }
 }
 
-fn file_name(source: &Source) -> String {
-    match source {
-        Source::Telegram {
-            account,
-            channel,
-            id: message_id,
-        } => format!(
-            "telegram-{}-{}-{message_id}.jpg",
-            channel.clone().unwrap_or_default(),
-            account.clone().unwrap_or_default()
-        ),
-        Source::Matrix { account, channel } => {
-            format!("matrix-{channel}-{account}")
+fn extension(mime_type: &str) -> &'static str {
+    match mime_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/heic" => "heic",
+        "image/avif" => "avif",
+        "image/bmp" => "bmp",
+        "image/tiff" => "tiff",
+        "video/mp4" => "mp4",
+        "video/webm" => "webm",
+        "video/quicktime" => "mov",
+        "application/x-tgsticker" => "tgs",
+        _ => "bin",
+    }
+}
+
+/// The MIME type of a stored file, going by its extension. Only
+/// needed for images stored before their contents were inspected.
+pub(crate) fn mime_type(file: &str) -> &'static str {
+    match file.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("jpg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("heic") => "image/heic",
+        Some("avif") => "image/avif",
+        Some("bmp") => "image/bmp",
+        Some("tiff") => "image/tiff",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mov") => "video/quicktime",
+        Some("tgs") => "application/x-tgsticker",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Files are stored under the SHA-256 hash of their contents, sharded
+/// into two levels of subdirectories, e.g. `ab/cd/abcd….jpg`.
+fn file_name(content_hash: &str, mime_type: &str) -> String {
+    format!(
+        "{}/{}/{content_hash}.{}",
+        &content_hash[0..2],
+        &content_hash[2..4],
+        extension(mime_type)
+    )
+}
+
+/// A file holding the data of an image, along with its hashes, what
+/// it turned out to contain, and the names and contents of its
+/// thumbnails.
+#[derive(Debug)]
+struct StoredFile {
+    file: String,
+    content_hash: String,
+    perceptual_hash: Option<i64>,
+    media: Media,
+    size: i64,
+    thumbnails: Vec<(String, Vec<u8>)>,
+}
+
+/// Ensure that `file` holds `data`, unless a file with identical
+/// contents already exists.
+async fn ensure_stored(storage: &Backend, file: &str, data: &[u8]) -> Result<()> {
+    if storage.exists(file).await? {
+        log::debug!("{file} already exists");
+    } else {
+        storage.put(file, data).await?;
+    }
+
+    Ok(())
+}
+
+/// Inspect, hash and store `image`, along with its thumbnails.
+/// Payloads that aren't media we know are rejected. Inspecting,
+/// hashing and scaling are CPU-bound, so they run on a blocking
+/// thread.
+async fn store_file(
+    storage: &Backend,
+    thumbnails: &Thumbnails,
+    image: &MemeImage,
+) -> Result<StoredFile> {
+    let data = image.data.clone();
+    let (content_hash, sniffed) =
+        spawn_blocking(move || (format!("{:x}", Sha256::digest(&data)), media::sniff(&data)))
+            .await?;
+
+    let Some((media, decoded)) = sniffed else {
+        return Err(Rejected {
+            file: file_name(&content_hash, &image.mime_type),
+            data: image.data.clone(),
         }
+        .into());
+    };
+    if media.mime_type != image.mime_type {
+        log::debug!(
+            "{content_hash} claimed to be {}, but is {}",
+            image.mime_type,
+            media.mime_type
+        );
     }
+
+    let file = file_name(&content_hash, media.mime_type);
+    ensure_stored(storage, &file, &image.data).await?;
+
+    // only images have a perceptual hash and thumbnails
+    let (perceptual_hash, generated) = match decoded {
+        Some(decoded) => {
+            spawn_blocking({
+                let (thumbnails, file) = (thumbnails.clone(), file.clone());
+                move || {
+                    (
+                        Some(reposts::perceptual_hash(&decoded)),
+                        thumbnails.generate(&file, &decoded),
+                    )
+                }
+            })
+            .await?
+        }
+        None => (None, Vec::new()),
+    };
+    // missing thumbnails can be regenerated later, so don't give up
+    // on the meme because of them
+    let mut stored = Vec::new();
+    for (name, data) in generated {
+        match ensure_stored(storage, &name, &data).await {
+            Ok(()) => stored.push((name, data)),
+            Err(err) => log::warn!("failed to store thumbnail {name}: {err}"),
+        }
+    }
+
+    Ok(StoredFile {
+        file,
+        content_hash,
+        perceptual_hash,
+        media,
+        size: image.data.len().try_into()?,
+        thumbnails: stored,
+    })
+}
+
+/// Run `f` with a connection from `pool` on a thread where blocking
+/// is fine, waiting for the database if necessary.
+async fn with_db<T: Send + 'static>(
+    pool: &Pool,
+    f: impl FnOnce(&mut AnyConnection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    with_db_until(pool, &Stopping::never(), f).await
 }
 
-async fn save_meme(
-    path: PathBuf,
-    db: &mut PgConnection,
-    image: MemeImage,
-    source: Source,
+/// Like [`with_db`], but stops waiting for the database once
+/// `stopping` is requested.
+async fn with_db_until<T: Send + 'static>(
+    pool: &Pool,
+    stopping: &Stopping,
+    f: impl FnOnce(&mut AnyConnection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let mut db = connection_until(pool, stopping).await?;
+
+    spawn_blocking(move || f(&mut db)).await?
+}
+
+/// Remove those of `files` that no image refers to anymore, along
+/// with their thumbnails. Only call this once the rows no longer
+/// referencing them are committed.
+async fn release_files(
+    storage: &Backend,
+    pool: &Pool,
+    stopping: &Stopping,
+    files: Vec<String>,
 ) -> Result<()> {
-    use db::{models::NewMeme, schema::memes};
+    use db::schema::images;
     use diesel::prelude::*;
-    log::debug!("saving meme: {source:?}");
 
-    let file = file_name(&source);
-    match source {
-        Source::Telegram {
-            account,
-            channel,
-            id: message_id,
-        } => {
-            let mut file_path = path.clone();
-            file_path.push(file.clone());
-            log::debug!("writing to {file_path:?}");
-            fs::write(file_path, &image.data).await?;
-
-            let new_meme = NewMeme {
-                spoiler: image.spoiler,
-                text: &image.text,
-                timestamp: image.timestamp,
-                account: &account.unwrap_or_default(),
-                channel: &channel.unwrap_or_default(),
-                filename: &file,
-                telegram_id: Some(message_id),
-            };
-            let result = insert_into(memes::table).values(&new_meme).execute(db);
-            log::debug!("inserted meme: {result:#?}");
+    let unreferenced = with_db_until(pool, stopping, move |db| {
+        let mut unreferenced = Vec::new();
+        for file in files.into_iter().unique() {
+            let references = images::table
+                .filter(images::filename.eq(&file))
+                .count()
+                .get_result::<i64>(db)?;
+
+            if references == 0 {
+                unreferenced.push(file);
+            }
         }
-        _ => todo!("Matrix is not yet supported"),
+
+        Ok(unreferenced)
+    })
+    .await?;
+
+    thumbnails::delete_all(storage, &unreferenced).await?;
+    for file in unreferenced {
+        storage.delete(&file).await?;
     }
 
     Ok(())
 }
 
-async fn update_meme(
-    path: PathBuf,
-    db: &mut PgConnection,
-    image: MemeImage,
-    source: Source,
-) -> Result<()> {
-    use db::schema::memes::dsl::{
-        account, channel, filename, memes, spoiler, telegram_id, text, timestamp,
+/// Undo storing `files` after their rows could not be written.
+/// Failures are only logged, since the original error matters more.
+async fn discard_files(storage: &Backend, pool: &Pool, stopping: &Stopping, files: Vec<String>) {
+    if let Err(err) = release_files(storage, pool, stopping, files).await {
+        log::error!("failed to remove unreferenced files: {err}");
+    }
+}
+
+// Files are always written before the rows referencing them are
+// committed, and only removed after those rows are gone. A crash in
+// between thus leaves at most an unreferenced file behind, but never
+// a row without its file.
+//
+// Handling an event is split into two stages: preparing stores the
+// files and may run concurrently for several events, while committing
+// writes the rows and releases files, one event at a time and in the
+// order the events arrived. All deletions of files happen while
+// committing, so that they can't race with other events.
+//
+// The functions below run inside a transaction and return the files
+// that might no longer be referenced afterwards.
+
+fn save_meme(
+    db: &mut AnyConnection,
+    images: &[(MemeImage, Source)],
+    files: &[Option<StoredFile>],
+    repost_threshold: Option<u32>,
+) -> Result<Vec<String>> {
+    use db::{
+        models::{NewImage, NewMeme},
+        schema::{images, memes},
+    };
+    use diesel::{prelude::*, result::DatabaseErrorKind};
+
+    let Some((first, source)) = images.first() else {
+        return Ok(Vec::new());
     };
+    log::debug!("saving meme: {source:?}");
+    if files.iter().all(Option::is_none) {
+        log::warn!("none of the images of {source:?} were kept");
+        return Ok(Vec::new());
+    }
+
+    // in an album, only one of the messages carries the caption
+    let text = images
+        .iter()
+        .map(|(image, _)| image.text.as_str())
+        .find(|text| !text.is_empty())
+        .unwrap_or_default();
+    let new_meme = NewMeme {
+        spoiler: images.iter().any(|(image, _)| image.spoiler),
+        text,
+        timestamp: first.timestamp,
+        account: source.account(),
+        channel: source.channel(),
+        telegram_id: source.telegram_id(),
+        matrix_id: source.matrix_id(),
+        telegram_chat_id: source.telegram_chat_id(),
+        telegram_chat_is_channel: source.telegram_chat_is_channel(),
+    };
+
+    // not every database supports `ON CONFLICT`, so try inserting in
+    // a savepoint and roll back if the meme already exists
+    let meme_id = match db.transaction(|db| {
+        insert_into(memes::table)
+            .values(&new_meme)
+            .returning(memes::id)
+            .get_result::<i32>(db)
+    }) {
+        Ok(meme_id) => meme_id,
+        Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            log::info!("meme {source:?} is already stored");
+            return Ok(files
+                .iter()
+                .flatten()
+                .map(|stored| stored.file.clone())
+                .collect());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // images with rejected payloads are left out, but their caption
+    // and spoiler still count for the meme
+    let kept = images
+        .iter()
+        .zip(files)
+        .filter_map(|(image, stored)| Some((image, stored.as_ref()?)));
+    let mut hashes = Vec::new();
+    for (position, ((image, source), stored)) in (0..).zip(kept) {
+        hashes.extend(stored.perceptual_hash);
+
+        let [hash_band_0, hash_band_1, hash_band_2, hash_band_3] =
+            reposts::bands(stored.perceptual_hash);
+        let new_image = NewImage {
+            meme_id,
+            position,
+            filename: &stored.file,
+            telegram_id: source.telegram_id(),
+            matrix_id: source.matrix_id(),
+            kind: image.kind.as_str(),
+            content_hash: &stored.content_hash,
+            perceptual_hash: stored.perceptual_hash,
+            mime_type: stored.media.mime_type,
+            width: stored.media.width,
+            height: stored.media.height,
+            size: stored.size,
+            hash_band_0,
+            hash_band_1,
+            hash_band_2,
+            hash_band_3,
+        };
+        insert_into(images::table).values(&new_image).execute(db)?;
+    }
+
+    if let Some(threshold) = repost_threshold {
+        reposts::detect(db, meme_id, &hashes, threshold)?;
+    }
+    tags::sync(db, meme_id, text)?;
+
+    log::debug!("inserted meme {meme_id}");
+    Ok(Vec::new())
+}
+
+/// Find the stored images that originate from `source`.
+fn find_images(db: &mut AnyConnection, source: &Source) -> Result<Vec<Image>> {
+    use db::schema::{images, memes};
     use diesel::prelude::*;
-    log::debug!("updating meme: {source:?}");
 
-    let file = file_name(&source);
-    match source {
+    let query = images::table
+        .inner_join(memes::table)
+        .select(Image::as_select())
+        .into_boxed();
+
+    let query = match source {
         Source::Telegram {
-            account: message_account,
-            channel: message_channel,
+            chat: message_chat,
             id: message_id,
+            ..
         } => {
-            let mut file_path = path.clone();
-            file_path.push(file.clone());
-            fs::write(file_path, &image.data).await?;
+            let query = query.filter(images::telegram_id.eq(Some(*message_id)));
 
-            update(memes.filter(telegram_id.eq(Some(message_id))))
+            // deletions outside of channels don't tell us the chat,
+            // but those message ids are unique for the whole account,
+            // as long as we leave channels and supergroups alone;
+            // memes stored before we knew the kind of chat are kept
+            match message_chat {
+                Some(message_chat) => query.filter(memes::telegram_chat_id.eq(*message_chat)),
+                None => query.filter(memes::telegram_chat_is_channel.eq(false)),
+            }
+        }
+        Source::Matrix { id: event_id, .. } => {
+            query.filter(images::matrix_id.eq(Some(event_id.as_str())))
+        }
+    };
+
+    Ok(query.load::<Image>(db)?)
+}
+
+fn update_meme(
+    db: &mut AnyConnection,
+    image: &MemeImage,
+    source: &Source,
+    file: Option<&StoredFile>,
+) -> Result<Vec<String>> {
+    use db::schema::{images, memes};
+    use diesel::prelude::*;
+    log::debug!("updating meme: {source:?}");
+
+    let found = find_images(db, source)?;
+    if found.is_empty() {
+        return Ok(file.map(|file| file.file.clone()).into_iter().collect());
+    }
+
+    for stored in &found {
+        // an edit with a rejected payload keeps the previous file,
+        // but may still change the caption
+        if let Some(file) = file {
+            let [hash_band_0, hash_band_1, hash_band_2, hash_band_3] =
+                reposts::bands(file.perceptual_hash);
+            update(images::table.find(stored.id))
                 .set((
-                    spoiler.eq(image.spoiler),
-                    text.eq(image.text),
-                    timestamp.eq(image.timestamp),
-                    account.eq(message_account.unwrap_or_default()),
-                    channel.eq(message_channel.unwrap_or_default()),
-                    filename.eq(file),
+                    images::filename.eq(&file.file),
+                    images::kind.eq(image.kind.as_str()),
+                    images::content_hash.eq(&file.content_hash),
+                    images::perceptual_hash.eq(file.perceptual_hash),
+                    images::mime_type.eq(file.media.mime_type),
+                    images::width.eq(file.media.width),
+                    images::height.eq(file.media.height),
+                    images::size.eq(file.size),
+                    images::hash_band_0.eq(hash_band_0),
+                    images::hash_band_1.eq(hash_band_1),
+                    images::hash_band_2.eq(hash_band_2),
+                    images::hash_band_3.eq(hash_band_3),
                 ))
                 .execute(db)?;
+
+            if stored.filename != file.file {
+                // have the new file recognised
+                update(images::table.find(stored.id))
+                    .set(images::ocr_text.eq(None::<String>))
+                    .execute(db)?;
+            }
+        }
+
+        update(memes::table.find(stored.meme_id))
+            .set((
+                memes::spoiler.eq(image.spoiler),
+                memes::timestamp.eq(image.timestamp),
+                memes::account.eq(source.account()),
+                memes::channel.eq(source.channel()),
+            ))
+            .execute(db)?;
+
+        // the caption of an album lives on a single message, so don't
+        // let edits to the other images clear it
+        if stored.position == 0 || !image.text.is_empty() {
+            update(memes::table.find(stored.meme_id))
+                .set(memes::text.eq(&image.text))
+                .execute(db)?;
+            tags::sync(db, stored.meme_id, &image.text)?;
         }
-        _ => todo!("Matrix is not yet supported"),
     }
 
-    Ok(())
+    Ok(found
+        .into_iter()
+        .map(|stored| stored.filename)
+        .filter(|filename| file.is_some_and(|file| filename != &file.file))
+        .collect())
 }
 
-async fn delete_meme(path: PathBuf, db: &mut PgConnection, source: Source) -> Result<()> {
-    use db::schema::memes::dsl::{filename, id, memes, telegram_id};
+fn delete_meme(db: &mut AnyConnection, source: &Source) -> Result<Vec<String>> {
+    use db::schema::{images, memes};
     use diesel::prelude::*;
 
     log::debug!("deleting meme: {source:?}");
 
-    match source {
-        Source::Telegram {
-            account: _,
-            channel: _,
-            id: message_id,
-        } => {
-            let files = memes
-                .select((id, filename))
-                .filter(telegram_id.eq(Some(message_id)))
-                .load::<(i32, String)>(db)?;
-
-            for (meme_id, file) in files {
-                let mut file_path = path.clone();
-                file_path.push(Path::new(&file));
-                fs::remove_file(file_path).await?;
-                delete(memes.find(meme_id)).execute(db)?;
+    let found = find_images(db, source)?;
+    for stored in &found {
+        delete(images::table.find(stored.id)).execute(db)?;
+
+        let remaining = images::table
+            .filter(images::meme_id.eq(stored.meme_id))
+            .count()
+            .get_result::<i64>(db)?;
+
+        if remaining == 0 {
+            delete(memes::table.find(stored.meme_id)).execute(db)?;
+        }
+    }
+
+    Ok(found.into_iter().map(|stored| stored.filename).collect())
+}
+
+fn react_to_meme(
+    db: &mut AnyConnection,
+    source: &Source,
+    reactions: &[Reaction],
+    timestamp: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let found = find_images(db, source)?;
+    if found.is_empty() {
+        log::debug!("ignoring reactions to unknown message {source:?}");
+    }
+    reactions::record(db, &found, reactions, timestamp)?;
+
+    Ok(Vec::new())
+}
+
+fn change_reactions(
+    db: &mut AnyConnection,
+    source: &Source,
+    changes: &[Reaction],
+    timestamp: NaiveDateTime,
+) -> Result<Vec<String>> {
+    let found = find_images(db, source)?;
+    if found.is_empty() {
+        log::debug!("ignoring reactions to unknown message {source:?}");
+    }
+    reactions::adjust(db, &found, changes, timestamp)?;
+
+    Ok(Vec::new())
+}
+
+/// How often to try handling an event before giving up on it.
+const ATTEMPTS: u32 = 4;
+/// How long to wait before the first retry, doubling for each further one.
+const BACKOFF: Duration = Duration::from_secs(1);
+/// How long to wait at most between attempts to reach the database.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How many events to prepare at the same time.
+const CONCURRENCY: usize = 4;
+
+/// Get a working connection from `pool`, waiting for the database to
+/// become available. Events are held back in the meantime.
+async fn connection(pool: &Pool) -> PooledConnection {
+    connection_until(pool, &Stopping::never())
+        .await
+        .expect("never stops waiting")
+}
+
+/// Like [`connection`], but gives up with [`Stopped`] once `stopping`
+/// is requested and the database is still unavailable.
+async fn connection_until(pool: &Pool, stopping: &Stopping) -> Result<PooledConnection> {
+    let mut delay = BACKOFF;
+
+    loop {
+        let pool = pool.clone();
+        match spawn_blocking(move || pool.get()).await {
+            Ok(Ok(connection)) => return Ok(connection),
+            Ok(Err(err)) => log::warn!("database unavailable, retrying in {delay:?}: {err}"),
+            Err(err) => log::warn!("failed to get a connection, retrying in {delay:?}: {err}"),
+        }
+
+        if stopping.requested() {
+            return Err(Stopped.into());
+        }
+        select! {
+            _ = sleep(delay) => {}
+            _ = stopping.wait() => {}
+        }
+        delay = (delay * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Whether the consumer has been asked to shut down or restart.
+#[derive(Clone, Debug)]
+struct Stopping(watch::Receiver<bool>);
+
+impl Stopping {
+    /// For one-off commands, which wait for the database for as long
+    /// as it takes.
+    fn never() -> Self {
+        Self(watch::Sender::new(false).subscribe())
+    }
+
+    fn requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once stopping is requested.
+    async fn wait(&self) {
+        if self.0.clone().wait_for(|stopping| *stopping).await.is_err() {
+            // nobody can ask us to stop anymore
+            std::future::pending().await
+        }
+    }
+}
+
+/// Waiting for the database was given up on, since the consumer is
+/// stopping.
+#[derive(Debug)]
+struct Stopped;
+
+impl Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stopped waiting for the database")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Store the files of all images of `event`. Doesn't stop at the
+/// first failure, so that committing can discard the other files.
+async fn prepare(
+    storage: &Backend,
+    thumbnails: &Thumbnails,
+    event: &MemeEvent,
+) -> Vec<Result<StoredFile>> {
+    let mut files = Vec::new();
+    for image in event.images() {
+        files.push(store_file(storage, thumbnails, image).await);
+    }
+
+    files
+}
+
+/// Write the rows for a prepared event and release files that are
+/// no longer needed. Images with rejected payloads are left out of
+/// the event and returned, so that they can be quarantined.
+async fn commit(
+    storage: &Backend,
+    pool: &Pool,
+    stopping: &Stopping,
+    repost_threshold: Option<u32>,
+    event: &Arc<MemeEvent>,
+    files: Vec<Result<StoredFile>>,
+) -> Result<Vec<Rejected>> {
+    log::debug!("new event: {event:#?}");
+
+    let mut stored = Vec::new();
+    let mut rejected = Vec::new();
+    let mut error = None;
+    for file in files {
+        match file {
+            Ok(file) => stored.push(Some(file)),
+            Err(err) => match err.downcast::<Rejected>() {
+                Ok(file) => {
+                    rejected.push(file);
+                    stored.push(None);
+                }
+                Err(err) => error = error.or(Some(err)),
+            },
+        }
+    }
+    let names = stored
+        .iter()
+        .flatten()
+        .map(|stored| stored.file.clone())
+        .collect::<Vec<_>>();
+
+    if let Some(err) = error {
+        discard_files(storage, pool, stopping, names).await;
+        return Err(err);
+    }
+
+    // a file shared with some other meme may have been released
+    // since it was stored
+    for (image, stored) in event.images().into_iter().zip(&stored) {
+        let Some(stored) = stored else {
+            continue;
+        };
+        ensure_stored(storage, &stored.file, &image.data).await?;
+        for (name, data) in &stored.thumbnails {
+            // just like in store_file, don't give up on the meme
+            // because of its thumbnails
+            if let Err(err) = ensure_stored(storage, name, data).await {
+                log::warn!("failed to store thumbnail {name}: {err}");
             }
         }
-        _ => todo!("Matrix is not yet supported"),
     }
 
-    Ok(())
+    let result = with_db_until(pool, stopping, {
+        let event = event.clone();
+        move |db| {
+            db.transaction(|db| match &*event {
+                MemeEvent::New { images } => save_meme(db, images, &stored, repost_threshold),
+                MemeEvent::Updated { image, source } => {
+                    update_meme(db, image, source, stored[0].as_ref())
+                }
+                MemeEvent::Deleted { source } => delete_meme(db, source),
+                MemeEvent::Reacted {
+                    source,
+                    reactions,
+                    timestamp,
+                } => react_to_meme(db, source, reactions, *timestamp),
+                MemeEvent::ReactionsChanged {
+                    source,
+                    changes,
+                    timestamp,
+                } => change_reactions(db, source, changes, *timestamp),
+            })
+        }
+    })
+    .await;
+
+    match result {
+        Ok(released) => {
+            release_files(storage, pool, stopping, released).await?;
+            Ok(rejected)
+        }
+        Err(err) => {
+            discard_files(storage, pool, stopping, names).await;
+            Err(err)
+        }
+    }
+}
+
+async fn handle_event(
+    storage: &Backend,
+    thumbnails: &Thumbnails,
+    pool: &Pool,
+    repost_threshold: Option<u32>,
+    event: &Arc<MemeEvent>,
+) -> Result<Vec<Rejected>> {
+    let files = prepare(storage, thumbnails, event).await;
+
+    commit(
+        storage,
+        pool,
+        &Stopping::never(),
+        repost_threshold,
+        event,
+        files,
+    )
+    .await
+}
+
+/// An event whose files have been stored, ready to be committed.
+struct Prepared {
+    event: Arc<MemeEvent>,
+    spooled: Option<Spooled>,
+    files: Vec<Result<StoredFile>>,
+}
+
+/// Prepare `queued` on a task of its own. If that task panics, the
+/// event is lost, unless it is spooled, in which case it is retried
+/// on the next start.
+fn prepare_queued(
+    storage: Arc<Backend>,
+    thumbnails: Arc<Thumbnails>,
+    queued: Queued,
+) -> impl Future<Output = Option<Prepared>> {
+    let task = tokio::spawn(async move {
+        let event = Arc::new(queued.event);
+        let files = prepare(&storage, &thumbnails, &event).await;
+
+        Prepared {
+            event,
+            spooled: queued.spooled,
+            files,
+        }
+    });
+
+    async move {
+        match task.await {
+            Ok(prepared) => Some(prepared),
+            Err(err) => {
+                log::error!("failed to prepare event: {err}");
+                None
+            }
+        }
+    }
+}
+
+/// Commit `event`, retrying with exponential backoff. Events that
+/// keep failing are set aside as dead letters, so that a single bad
+/// event doesn't stop the consumer. Images with rejected payloads are
+/// left out of the event and quarantined instead. Fails only if
+/// the event is lost, or with [`Stopped`] if the consumer stopped
+/// while waiting for the database. Waiting for the database doesn't
+/// count as an attempt.
+async fn handle_with_retries(
+    storage: &Backend,
+    thumbnails: &Thumbnails,
+    pool: &Pool,
+    stopping: &Stopping,
+    repost_threshold: Option<u32>,
+    dead_letters: &DeadLetters,
+    event: &Arc<MemeEvent>,
+    mut files: Vec<Result<StoredFile>>,
+) -> Result<()> {
+    let mut delay = BACKOFF;
+
+    for attempt in 1..ATTEMPTS {
+        match commit(storage, pool, stopping, repost_threshold, event, files).await {
+            Ok(rejected) => return dead_letters.reject(&rejected).await,
+            Err(err) => {
+                if err.is::<Stopped>() {
+                    return Err(err);
+                }
+                log::warn!("attempt {attempt} of {ATTEMPTS} failed, retrying in {delay:?}: {err}");
+                sleep(delay).await;
+                delay *= 2;
+                files = prepare(storage, thumbnails, event).await;
+            }
+        }
+    }
+
+    match commit(storage, pool, stopping, repost_threshold, event, files).await {
+        Ok(rejected) => dead_letters.reject(&rejected).await,
+        Err(err) if err.is::<Stopped>() => Err(err),
+        Err(err) => {
+            log::error!("giving up on event: {err}");
+            dead_letters
+                .store(event, &err)
+                .await
+                .map_err(|err| anyhow!("failed to store dead letter for {event:?}: {err}"))
+        }
+    }
+}
+
+/// Commit a prepared event and acknowledge it, unless it was lost.
+async fn settle(
+    storage: &Backend,
+    thumbnails: &Thumbnails,
+    pool: &Pool,
+    stopping: &Stopping,
+    repost_threshold: Option<u32>,
+    dead_letters: &DeadLetters,
+    prepared: Prepared,
+) -> Option<Spooled> {
+    let Prepared {
+        event,
+        spooled,
+        files,
+    } = prepared;
+
+    match handle_with_retries(
+        storage,
+        thumbnails,
+        pool,
+        stopping,
+        repost_threshold,
+        dead_letters,
+        &event,
+        files,
+    )
+    .await
+    {
+        Ok(()) => {
+            if let Some((spool, id)) = spooled
+                && let Err(err) = spool.remove(&id).await
+            {
+                log::error!("failed to remove spooled event {id}: {err}");
+            }
+            None
+        }
+        Err(err) if err.is::<Stopped>() => match spooled {
+            Some((spool, id)) => {
+                log::warn!("leaving spooled event {id} for later: {err}");
+                Some((spool, id))
+            }
+            None => {
+                if let Err(err) = dead_letters.store(&event, &err).await {
+                    log::error!("failed to store dead letter for {event:?}: {err}");
+                }
+                None
+            }
+        },
+        // spooled events are kept around to be retried on reload or restart
+        Err(err) => {
+            log::error!("{err}");
+            spooled
+        }
+    }
 }
 
 async fn process(
     storage: StorageConfiguration,
+    processing: ProcessingConfiguration,
     database: DatabaseConfiguration,
     mut control: Receiver<Command>,
-    mut consumer: Receiver<MemeEvent>,
+    mut consumer: Receiver<Queued>,
+    backlog: Vec<Spooled>,
+    stopping: Stopping,
 ) -> TaskResult {
     log::info!("starting storage");
 
-    let path = storage.path().to_path_buf();
-    let mut db = db::connect(database.url())?;
+    let repost_threshold = processing.repost_threshold();
+    let dead_letters = DeadLetters::new(&storage);
+    let ocr = processing.ocr().map(ocr::Ocr::new);
+    let thumbnails = Arc::new(Thumbnails::new(processing.thumbnails()));
+    let storage = Arc::new(Backend::open(&storage)?);
+    let pool = db::pool(database.url());
+    match connection_until(&pool, &stopping).await {
+        Ok(mut connection) => db::migrate(&mut *connection)?,
+        Err(err) if err.is::<Stopped>() => {
+            // queued events are left for whoever takes over
+            log::warn!("stopping before the database became available");
+            control.recv().await;
+            return Ok((control, consumer, backlog));
+        }
+        Err(err) => return Err(err),
+    }
     log::debug!("connected to database");
+    let ocr =
+        ocr.map(|ocr| ocr::Worker::spawn(ocr, storage.clone(), pool.clone(), stopping.clone()));
+    let wake_ocr = || {
+        if let Some(ref ocr) = ocr {
+            ocr.wake();
+        }
+    };
 
-    async fn handle_event(path: PathBuf, db: &mut PgConnection, event: MemeEvent) -> Result<()> {
-        log::debug!("new event: {event:#?}");
-        match event {
-            MemeEvent::New { image, source } => save_meme(path, db, image, source).await?,
-            MemeEvent::Updated { image, source } => update_meme(path, db, image, source).await?,
-            MemeEvent::Deleted { source } => delete_meme(path, db, source).await?,
-        };
+    if !backlog.is_empty() {
+        log::info!("resuming {} spooled events", backlog.len());
+    }
+    let mut pending = Vec::new();
+    let mut backlog = backlog.into_iter();
+    while let Some((spool, id)) = backlog.next() {
+        // whatever is left stays in the spool
+        if stopping.requested() {
+            pending.push((spool, id));
+            pending.extend(backlog);
+            break;
+        }
 
-        Ok(())
+        match spool.load::<MemeEvent>(&id).await {
+            Ok(event) => {
+                let queued = Queued {
+                    event,
+                    spooled: Some((spool, id)),
+                };
+                if let Some(prepared) =
+                    prepare_queued(storage.clone(), thumbnails.clone(), queued).await
+                {
+                    pending.extend(
+                        settle(
+                            &storage,
+                            &thumbnails,
+                            &pool,
+                            &stopping,
+                            repost_threshold,
+                            &dead_letters,
+                            prepared,
+                        )
+                        .await,
+                    );
+                    wake_ocr();
+                }
+            }
+            Err(err) => log::error!("skipping unreadable spooled event {id}: {err}"),
+        }
     }
 
+    let mut preparing = FuturesOrdered::new();
+
     loop {
         select! {
-            Some(event) = consumer.recv() => {
-                handle_event(path.clone(), &mut db, event).await?;
+            Some(queued) = consumer.recv(), if preparing.len() < CONCURRENCY => {
+                preparing.push_back(prepare_queued(storage.clone(), thumbnails.clone(), queued));
+            }
+
+            Some(prepared) = preparing.next() => {
+                if let Some(prepared) = prepared {
+                    pending.extend(settle(&storage, &thumbnails, &pool, &stopping, repost_threshold, &dead_letters, prepared).await);
+                    wake_ocr();
+                }
             }
 
             Some(command) = control.recv() => {
                 match command {
-                    Command::Shutdown => break
+                    Command::Shutdown => {
+                        // don't lose memes that have already been queued
+                        while let Ok(queued) = consumer.try_recv() {
+                            preparing.push_back(prepare_queued(storage.clone(), thumbnails.clone(), queued));
+                        }
+                        while let Some(prepared) = preparing.next().await {
+                            if let Some(prepared) = prepared {
+                                pending.extend(settle(&storage, &thumbnails, &pool, &stopping, repost_threshold, &dead_letters, prepared).await);
+                            }
+                        }
+                        break
+                    }
                 }
             }
         }
     }
 
-    Ok((control, consumer))
+    if let Some(ocr) = ocr {
+        ocr.stop().await;
+    }
+
+    Ok((control, consumer, pending))
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::NaiveDateTime;
+    use diesel::prelude::*;
+    use test_log::test;
+
+    use super::{
+        MemeEvent, Reaction, Source, Thumbnails, connection, db, handle_event,
+        media::test::{database, image, png},
+        reactions, search, tags,
+    };
+    use crate::{
+        config::ThumbnailConfiguration,
+        storage::{Backend, LocalStorage, Storage},
+    };
+
+    fn album() -> MemeEvent {
+        MemeEvent::album(vec![
+            (
+                image(&png(1, 1), "caption #Cats"),
+                Source::matrix(None, None, "$first"),
+            ),
+            (image(&png(2, 1), ""), Source::matrix(None, None, "$second")),
+        ])
+    }
+
+    #[test(tokio::test)]
+    async fn sqlite_round_trip() {
+        use db::schema::{images, memes};
+
+        let files = tempfile::tempdir().expect("can create directory");
+        let storage = Backend::Local(LocalStorage::new(files.path()));
+        let database = database().await;
+        let pool = &database.pool;
+        let thumbnails = Thumbnails::default();
+
+        let count = async || {
+            let mut db = connection(pool).await;
+            (
+                memes::table
+                    .count()
+                    .get_result::<i64>(&mut *db)
+                    .expect("can count"),
+                images::table
+                    .count()
+                    .get_result::<i64>(&mut *db)
+                    .expect("can count"),
+            )
+        };
+        let handle = async |event| {
+            handle_event(&storage, &thumbnails, pool, Some(4), &Arc::new(event))
+                .await
+                .expect("can handle event")
+        };
+
+        handle(album()).await;
+        handle(album()).await;
+        assert_eq!(count().await, (1, 2));
+        assert_eq!(storage.list().await.expect("can list").len(), 2);
+
+        handle(MemeEvent::edit(
+            image(&png(3, 1), ""),
+            Source::matrix(None, None, "$second"),
+        ))
+        .await;
+        assert_eq!(storage.list().await.expect("can list").len(), 2);
+        let text = memes::table
+            .select(memes::text)
+            .first::<String>(&mut *connection(pool).await)
+            .expect("can load");
+        assert_eq!(text, "caption #Cats");
+        let meme = memes::table
+            .select(memes::id)
+            .first::<i32>(&mut *connection(pool).await)
+            .expect("can load");
+        let tags_of = async || tags::of_meme(&mut *connection(pool).await, meme).expect("can load");
+        assert_eq!(tags_of().await, vec!["cats"]);
+        let search = async |text: &str| {
+            let query = search::Query {
+                text: text.to_string(),
+                limit: 10,
+                ..Default::default()
+            };
+            search::find(&mut *connection(pool).await, &query)
+                .expect("can search")
+                .len()
+        };
+        assert_eq!(search("CAPTION cats").await, 1);
+        assert_eq!(search("caption dogs").await, 0);
+        assert_eq!(search("dogs or cats").await, 1);
+        assert_eq!(search("caption -cats").await, 0);
+        assert_eq!(search(r#""caption #cats""#).await, 1);
+        assert_eq!(search("cap%").await, 0);
+
+        handle(MemeEvent::edit(
+            image(&png(1, 1), "#birds and #cats"),
+            Source::matrix(None, None, "$first"),
+        ))
+        .await;
+        assert_eq!(tags_of().await, vec!["birds", "cats"]);
+        assert_eq!(
+            tags::counts(&mut *connection(pool).await).expect("can count"),
+            vec![("birds".to_string(), 1), ("cats".to_string(), 1)]
+        );
+
+        let react = |id, reactions: &[(&str, i32)]| {
+            MemeEvent::react(
+                Source::matrix(None, None, id),
+                reactions
+                    .iter()
+                    .map(|(emoji, count)| Reaction::new(emoji.to_string(), *count))
+                    .collect(),
+                NaiveDateTime::default(),
+            )
+        };
+        let reactions_of =
+            async || reactions::of_meme(&mut *connection(pool).await, meme).expect("can load");
+        handle(react("$first", &[("👍", 3), ("😂", 1)])).await;
+        handle(react("$second", &[("👍", 2)])).await;
+        assert_eq!(
+            reactions_of().await,
+            vec![("👍".to_string(), 5), ("😂".to_string(), 1)]
+        );
+        handle(react("$first", &[("👍", 1), ("😂", 0)])).await;
+        assert_eq!(reactions_of().await, vec![("👍".to_string(), 3)]);
+        let change = |changes: &[(&str, i32)]| {
+            MemeEvent::change_reactions(
+                Source::matrix(None, None, "$first"),
+                changes
+                    .iter()
+                    .map(|(emoji, count)| Reaction::new(emoji.to_string(), *count))
+                    .collect(),
+                NaiveDateTime::default(),
+            )
+        };
+        handle(change(&[("👍", -1), ("🔥", 1)])).await;
+        assert_eq!(
+            reactions_of().await,
+            vec![("👍".to_string(), 2), ("🔥".to_string(), 1)]
+        );
+        handle(change(&[("🔥", -1), ("👍", 1)])).await;
+        assert_eq!(reactions_of().await, vec![("👍".to_string(), 3)]);
+        let best = reactions::best_of(
+            &mut *connection(pool).await,
+            NaiveDateTime::default()..=NaiveDateTime::default(),
+            10,
+        )
+        .expect("can load");
+        assert_eq!(
+            best.iter()
+                .map(|(meme, count)| (meme.id, *count))
+                .collect::<Vec<_>>(),
+            vec![(meme, 3)]
+        );
+
+        handle(MemeEvent::delete(Source::matrix(None, None, "$first"))).await;
+        assert_eq!(count().await, (1, 1));
+        assert_eq!(reactions_of().await, vec![("👍".to_string(), 2)]);
+        handle(MemeEvent::delete(Source::matrix(None, None, "$second"))).await;
+        assert_eq!(count().await, (0, 0));
+        assert!(storage.list().await.expect("can list").is_empty());
+    }
+
+    #[test(tokio::test)]
+    async fn thumbnails_follow_their_images() {
+        let files = tempfile::tempdir().expect("can create directory");
+        let storage = Backend::Local(LocalStorage::new(files.path()));
+        let database = database().await;
+        let pool = &database.pool;
+        let config = serde_json::from_str::<ThumbnailConfiguration>(r#"{"sizes": [8, 16]}"#)
+            .expect("can parse");
+        let thumbnails = Thumbnails::new(Some(&config));
+
+        let handle = async |event| {
+            handle_event(&storage, &thumbnails, pool, None, &Arc::new(event))
+                .await
+                .expect("can handle event")
+        };
+        let listed = async || {
+            let mut files = storage.list().await.expect("can list");
+            files.sort();
+            files
+        };
+
+        handle(MemeEvent::new(
+            image(&png(32, 32), ""),
+            Source::matrix(None, None, "$meme"),
+        ))
+        .await;
+        let original = listed().await.remove(0);
+        assert_eq!(
+            listed().await,
+            vec![
+                original.clone(),
+                format!("thumbnails/16/{original}.jpg"),
+                format!("thumbnails/8/{original}.jpg"),
+            ]
+        );
+
+        handle(MemeEvent::edit(
+            image(&png(64, 32), ""),
+            Source::matrix(None, None, "$meme"),
+        ))
+        .await;
+        let edited = listed().await;
+        assert_eq!(edited.len(), 3);
+        assert!(!edited.contains(&original));
+
+        handle(MemeEvent::delete(Source::matrix(None, None, "$meme"))).await;
+        assert!(listed().await.is_empty());
+    }
+
+    #[test(tokio::test)]
+    async fn rejects_unknown_payloads() {
+        use db::schema::{images, memes};
+
+        let files = tempfile::tempdir().expect("can create directory");
+        let storage = Backend::Local(LocalStorage::new(files.path()));
+        let database = database().await;
+        let pool = &database.pool;
+
+        let handle = async |event| {
+            handle_event(
+                &storage,
+                &Thumbnails::default(),
+                pool,
+                None,
+                &Arc::new(event),
+            )
+            .await
+            .expect("can handle event")
+        };
+        let broken = |text: &str| image(b"<html>not found</html>", text);
+        let text = async || {
+            memes::table
+                .select(memes::text)
+                .first::<String>(&mut *connection(pool).await)
+                .expect("can load")
+        };
+
+        let rejected = handle(MemeEvent::new(
+            broken(""),
+            Source::matrix(None, None, "$meme"),
+        ))
+        .await;
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].file.ends_with(".png"));
+        assert!(storage.list().await.expect("can list").is_empty());
+
+        // the other images of an album are kept, along with its caption
+        let rejected = handle(MemeEvent::album(vec![
+            (broken("caption"), Source::matrix(None, None, "$first")),
+            (image(&png(1, 1), ""), Source::matrix(None, None, "$second")),
+        ]))
+        .await;
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(text().await, "caption");
+        let count = images::table
+            .count()
+            .get_result::<i64>(&mut *connection(pool).await)
+            .expect("can count");
+        assert_eq!(count, 1);
+        assert_eq!(storage.list().await.expect("can list").len(), 1);
+
+        // edits keep the previous file, but still change the caption
+        let rejected = handle(MemeEvent::edit(
+            broken("edited"),
+            Source::matrix(None, None, "$second"),
+        ))
+        .await;
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(text().await, "edited");
+        assert_eq!(storage.list().await.expect("can list").len(), 1);
+    }
 }
@@ -0,0 +1,408 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{ops::RangeInclusive, sync::Arc};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use super::{
+    db::{
+        self, AnyConnection, Pool,
+        models::{Image, Meme},
+    },
+    mime_type, reactions, reposts,
+    search::{Query, find},
+    tags,
+    thumbnails::{self, Thumbnails},
+    with_db,
+};
+use crate::{
+    config::{DatabaseConfiguration, ProcessingConfiguration, StorageConfiguration},
+    storage::{Backend, Storage},
+};
+
+/// Read-only access to the stored memes and their files.
+#[derive(Clone)]
+pub(crate) struct Archive {
+    storage: Arc<Backend>,
+    thumbnails: Thumbnails,
+    pool: Pool,
+}
+
+/// A stored meme, along with everything known about it.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Entry {
+    pub(crate) id: i32,
+    pub(crate) spoiler: bool,
+    pub(crate) text: String,
+    pub(crate) timestamp: NaiveDateTime,
+    pub(crate) account: String,
+    pub(crate) channel: String,
+    pub(crate) repost_of: Option<i32>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) reactions: Vec<EntryReaction>,
+    pub(crate) images: Vec<EntryImage>,
+}
+
+/// A stored meme, along with its total number of reactions.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RankedEntry {
+    #[serde(flatten)]
+    pub(crate) entry: Entry,
+    pub(crate) total_reactions: i64,
+}
+
+/// A tag that is in use, and by how many memes.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TagCount {
+    pub(crate) name: String,
+    pub(crate) count: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EntryReaction {
+    pub(crate) emoji: String,
+    pub(crate) count: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct EntryImage {
+    pub(crate) file: String,
+    pub(crate) kind: String,
+    pub(crate) mime_type: String,
+    pub(crate) width: Option<i32>,
+    pub(crate) height: Option<i32>,
+    /// in bytes
+    pub(crate) size: Option<i64>,
+    /// largest first; may not exist yet for images stored before
+    /// thumbnails were enabled
+    pub(crate) thumbnails: Vec<String>,
+}
+
+impl Entry {
+    fn load(db: &mut AnyConnection, thumbnails: &Thumbnails, meme: Meme) -> Result<Self> {
+        use db::schema::images;
+        use diesel::prelude::*;
+
+        let images = images::table
+            .filter(images::meme_id.eq(meme.id))
+            .order(images::position)
+            .select(Image::as_select())
+            .load(db)?;
+
+        Ok(Self {
+            tags: tags::of_meme(db, meme.id)?,
+            reactions: reactions::of_meme(db, meme.id)?
+                .into_iter()
+                .map(|(emoji, count)| EntryReaction { emoji, count })
+                .collect(),
+            images: images
+                .into_iter()
+                .map(|image| {
+                    let mime_type = image
+                        .mime_type
+                        .unwrap_or_else(|| mime_type(&image.filename).to_string());
+                    EntryImage {
+                        thumbnails: if mime_type.starts_with("image/") {
+                            thumbnails.names(&image.filename)
+                        } else {
+                            Vec::new()
+                        },
+                        mime_type,
+                        width: image.width,
+                        height: image.height,
+                        size: image.size,
+                        file: image.filename,
+                        kind: image.kind,
+                    }
+                })
+                .collect(),
+            id: meme.id,
+            spoiler: meme.spoiler,
+            text: meme.text,
+            timestamp: meme.timestamp,
+            account: meme.account,
+            channel: meme.channel,
+            repost_of: meme.repost_of,
+        })
+    }
+}
+
+impl Archive {
+    /// Doesn't migrate the database, that's up to the consumer.
+    pub(crate) fn open(
+        storage: &StorageConfiguration,
+        processing: &ProcessingConfiguration,
+        database: &DatabaseConfiguration,
+    ) -> Result<Self> {
+        Ok(Self {
+            thumbnails: Thumbnails::new(processing.thumbnails()),
+            storage: Arc::new(Backend::open(storage)?),
+            pool: db::pool(database.url()),
+        })
+    }
+
+    /// The memes matching `query`, best matches first.
+    pub(crate) async fn memes(&self, query: Query) -> Result<Vec<Entry>> {
+        let thumbnails = self.thumbnails.clone();
+        with_db(&self.pool, move |db| {
+            find(db, &query)?
+                .into_iter()
+                .map(|meme| Entry::load(db, &thumbnails, meme))
+                .collect()
+        })
+        .await
+    }
+
+    /// The meme with id `id`, if there is one.
+    pub(crate) async fn meme(&self, id: i32) -> Result<Option<Entry>> {
+        use db::schema::memes;
+        use diesel::prelude::*;
+
+        let thumbnails = self.thumbnails.clone();
+        with_db(&self.pool, move |db| {
+            memes::table
+                .find(id)
+                .select(Meme::as_select())
+                .first(db)
+                .optional()?
+                .map(|meme| Entry::load(db, &thumbnails, meme))
+                .transpose()
+        })
+        .await
+    }
+
+    /// All variants of the meme with id `id`, i.e., its original and
+    /// all reposts of that, oldest first. Empty if there is no such
+    /// meme.
+    pub(crate) async fn variants(&self, id: i32) -> Result<Vec<Entry>> {
+        let thumbnails = self.thumbnails.clone();
+        with_db(&self.pool, move |db| {
+            reposts::variants(db, id)?
+                .into_iter()
+                .map(|meme| Entry::load(db, &thumbnails, meme))
+                .collect()
+        })
+        .await
+    }
+
+    /// The `limit` memes posted during `period` with the most
+    /// reactions, most reactions first.
+    pub(crate) async fn best_of(
+        &self,
+        period: RangeInclusive<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<RankedEntry>> {
+        let thumbnails = self.thumbnails.clone();
+        with_db(&self.pool, move |db| {
+            reactions::best_of(db, period, limit)?
+                .into_iter()
+                .map(|(meme, total_reactions)| {
+                    Ok(RankedEntry {
+                        entry: Entry::load(db, &thumbnails, meme)?,
+                        total_reactions,
+                    })
+                })
+                .collect()
+        })
+        .await
+    }
+
+    /// All tags that are in use, most used first.
+    pub(crate) async fn tags(&self) -> Result<Vec<TagCount>> {
+        with_db(&self.pool, |db| {
+            Ok(tags::counts(db)?
+                .into_iter()
+                .map(|(name, count)| TagCount { name, count })
+                .collect())
+        })
+        .await
+    }
+
+    /// The contents of `file`, if an image refers to it or it is a
+    /// thumbnail of such a file. Other files in the storage are never
+    /// handed out.
+    pub(crate) async fn file(&self, file: &str) -> Result<Option<Vec<u8>>> {
+        use db::schema::images;
+        use diesel::{dsl::exists, prelude::*, select};
+
+        let original = thumbnails::original(file);
+        let referenced = with_db(&self.pool, {
+            let file = original.unwrap_or(file).to_string();
+            move |db| {
+                Ok(
+                    select(exists(images::table.filter(images::filename.eq(file))))
+                        .get_result::<bool>(db)?,
+                )
+            }
+        })
+        .await?;
+
+        if !referenced || (original.is_some() && !self.storage.exists(file).await?) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.storage.get(file).await?))
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use std::sync::Arc;
+
+    use chrono::NaiveDateTime;
+    use test_log::test;
+
+    use super::{Archive, Thumbnails};
+    use crate::{
+        consumer::{
+            MediaKind, MemeEvent, MemeImage, Source, handle_event,
+            media::test::{database, image, png},
+            search::Query,
+        },
+        storage::{Backend, LocalStorage},
+    };
+
+    #[test(tokio::test)]
+    async fn lists_memes() {
+        let files = tempfile::tempdir().expect("can create directory");
+        let database = database().await;
+        let archive = Archive {
+            storage: Arc::new(Backend::Local(LocalStorage::new(files.path()))),
+            thumbnails: Thumbnails::default(),
+            pool: database.pool.clone(),
+        };
+
+        for (id, text) in ["first #koma", "second", "third #KoMa"]
+            .into_iter()
+            .enumerate()
+        {
+            let image = MemeImage::new(
+                png(id as u32 + 1, 1),
+                MediaKind::Photo,
+                "image/jpeg".to_string(),
+                id == 0,
+                text.to_string(),
+                NaiveDateTime::default(),
+            );
+            let event = MemeEvent::new(image, Source::matrix(None, None, &format!("${id}")));
+            handle_event(
+                &archive.storage,
+                &archive.thumbnails,
+                &archive.pool,
+                None,
+                &Arc::new(event),
+            )
+            .await
+            .expect("can store");
+        }
+
+        let texts = async |query| {
+            archive
+                .memes(query)
+                .await
+                .expect("can list")
+                .into_iter()
+                .map(|entry| entry.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(Query {
+                tag: Some("#koma".to_string()),
+                limit: 10,
+                ..Default::default()
+            })
+            .await,
+            vec!["first #koma", "third #KoMa"]
+        );
+        assert_eq!(
+            texts(Query {
+                tag: Some("koma".to_string()),
+                limit: 10,
+                offset: 1,
+                ..Default::default()
+            })
+            .await
+            .len(),
+            1
+        );
+        let tags = archive.tags().await.expect("can list");
+        assert_eq!(
+            tags.iter()
+                .map(|tag| (tag.name.as_str(), tag.count))
+                .collect::<Vec<_>>(),
+            vec![("koma", 2)]
+        );
+
+        let entry = archive.meme(1).await.expect("can load").expect("exists");
+        assert!(entry.spoiler);
+        assert_eq!(entry.tags, vec!["koma"]);
+        assert_eq!(entry.images[0].mime_type, "image/png");
+        assert!(entry.images[0].file.ends_with(".png"));
+        assert_eq!(
+            (entry.images[0].width, entry.images[0].height),
+            (Some(1), Some(1))
+        );
+        assert_eq!(
+            archive.file(&entry.images[0].file).await.expect("can read"),
+            Some(png(1, 1))
+        );
+        assert!(archive.variants(23).await.expect("can load").is_empty());
+        assert!(archive.meme(23).await.expect("can load").is_none());
+        assert!(
+            archive
+                .file("../memes.db")
+                .await
+                .expect("can check")
+                .is_none()
+        );
+    }
+
+    #[test(tokio::test)]
+    async fn lists_variants() {
+        let files = tempfile::tempdir().expect("can create directory");
+        let database = database().await;
+        let archive = Archive {
+            storage: Arc::new(Backend::Local(LocalStorage::new(files.path()))),
+            thumbnails: Thumbnails::default(),
+            pool: database.pool.clone(),
+        };
+
+        // all black images look the same
+        for (id, size) in [8, 16, 32].into_iter().enumerate() {
+            let event = MemeEvent::new(
+                image(&png(size, size), ""),
+                Source::matrix(None, None, &format!("${id}")),
+            );
+            handle_event(
+                &archive.storage,
+                &archive.thumbnails,
+                &archive.pool,
+                Some(4),
+                &Arc::new(event),
+            )
+            .await
+            .expect("can store");
+        }
+
+        for id in [1, 3] {
+            let variants = archive.variants(id).await.expect("can load");
+            assert_eq!(
+                variants
+                    .iter()
+                    .map(|entry| (entry.id, entry.repost_of))
+                    .collect::<Vec<_>>(),
+                vec![(1, None), (2, Some(1)), (3, Some(1))]
+            );
+        }
+    }
+}
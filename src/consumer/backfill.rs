@@ -0,0 +1,153 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use anyhow::Result;
+use diesel::dsl::{insert_into, update};
+
+use super::{
+    db::{self, Pool},
+    with_db,
+};
+use crate::config::DatabaseConfiguration;
+
+/// Keeps track of how far the backfill of a Telegram group has
+/// progressed, so that it can be interrupted and resumed later.
+pub(crate) struct BackfillProgress {
+    pool: Pool,
+    chat: i64,
+    /// the name of the group, which memes stored before we kept
+    /// track of chats are known by
+    channel: String,
+    message: Option<i32>,
+}
+
+impl BackfillProgress {
+    pub(crate) async fn load(
+        database: &DatabaseConfiguration,
+        chat: i64,
+        channel: String,
+    ) -> Result<Self> {
+        use db::schema::backfill_progress::dsl::{backfill_progress, chat_id, message_id};
+        use diesel::prelude::*;
+
+        let pool = db::pool(database.url());
+        let message = with_db(&pool, move |db| {
+            db::migrate(db)?;
+
+            Ok(backfill_progress
+                .select(message_id)
+                .filter(chat_id.eq(chat))
+                .first::<i32>(db)
+                .optional()?)
+        })
+        .await?;
+
+        Ok(Self {
+            pool,
+            chat,
+            channel,
+            message,
+        })
+    }
+
+    /// The last message that has already been processed, if any.
+    pub(crate) fn last_message(&self) -> Option<i32> {
+        self.message
+    }
+
+    pub(crate) async fn is_stored(&self, message: i32) -> Result<bool> {
+        use db::schema::{images, memes};
+        use diesel::prelude::*;
+
+        let (chat, channel) = (self.chat, self.channel.clone());
+        // messages of an album are stored as images of a single meme
+        let count = with_db(&self.pool, move |db| {
+            Ok(images::table
+                .inner_join(memes::table)
+                .filter(
+                    memes::telegram_chat_id.eq(chat).or(memes::telegram_chat_id
+                        .is_null()
+                        .and(memes::channel.eq(channel))),
+                )
+                .filter(images::telegram_id.eq(Some(message)))
+                .count()
+                .get_result::<i64>(db)?)
+        })
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    pub(crate) async fn save(&mut self, message: i32) -> Result<()> {
+        use db::schema::backfill_progress::dsl::{backfill_progress, chat_id, message_id};
+        use diesel::prelude::*;
+
+        let chat = self.chat;
+        with_db(&self.pool, move |db| {
+            let updated = update(backfill_progress.find(chat))
+                .set(message_id.eq(message))
+                .execute(db)?;
+            if updated == 0 {
+                insert_into(backfill_progress)
+                    .values((chat_id.eq(chat), message_id.eq(message)))
+                    .execute(db)?;
+            }
+
+            Ok(())
+        })
+        .await?;
+        self.message = Some(message);
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod test {
+    use std::sync::Arc;
+
+    use test_log::test;
+
+    use super::BackfillProgress;
+    use crate::{
+        consumer::{
+            MemeEvent, Source, Thumbnails, handle_event,
+            media::test::{database, image, png},
+        },
+        storage::{Backend, LocalStorage},
+    };
+
+    #[test(tokio::test)]
+    async fn legacy_memes_are_stored() {
+        let files = tempfile::tempdir().expect("can create directory");
+        let storage = Backend::Local(LocalStorage::new(files.path()));
+        let database = database().await;
+
+        // memes stored before we kept track of chats have none
+        let event = MemeEvent::new(
+            image(&png(1, 1), ""),
+            Source::telegram(None, Some("KoMa"), None, false, 5),
+        );
+        handle_event(
+            &storage,
+            &Thumbnails::default(),
+            &database.pool,
+            None,
+            &Arc::new(event),
+        )
+        .await
+        .expect("can handle event");
+
+        let progress = |channel: &str| BackfillProgress {
+            pool: database.pool.clone(),
+            chat: -100,
+            channel: channel.to_string(),
+            message: None,
+        };
+        assert!(progress("KoMa").is_stored(5).await.expect("can check"));
+        assert!(!progress("KoMa").is_stored(6).await.expect("can check"));
+        assert!(!progress("other").is_stored(5).await.expect("can check"));
+    }
+}
@@ -7,24 +7,126 @@ pub(super) mod models;
 pub(super) mod schema;
 
 use anyhow::{Result, anyhow};
-use diesel::{Connection, PgConnection, pg::Pg};
+use diesel::{
+    Connection, ConnectionError, ConnectionResult, PgConnection,
+    backend::Backend,
+    r2d2::{self, R2D2Connection},
+};
+#[cfg(feature = "sqlite")]
+use diesel::{SqliteConnection, connection::SimpleConnection};
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
-use std::error::Error;
+use std::{error::Error, time::Duration};
+
+/// A connection to any of the supported databases.
+#[derive(diesel::MultiConnection)]
+pub(crate) enum AnyConnection {
+    Postgresql(PgConnection),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteConnection),
+}
+
+pub(crate) type Pool = r2d2::Pool<ConnectionManager>;
+pub(crate) type PooledConnection = r2d2::PooledConnection<ConnectionManager>;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations-sqlite");
 
-fn run_migrations(
-    connection: &mut impl MigrationHarness<Pg>,
+fn run_migrations<DB: Backend>(
+    connection: &mut impl MigrationHarness<DB>,
+    migrations: EmbeddedMigrations,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    connection.run_pending_migrations(MIGRATIONS)?;
+    connection.run_pending_migrations(migrations)?;
 
     Ok(())
 }
 
-pub(crate) fn connect(url: &str) -> Result<PgConnection> {
-    let mut connection = PgConnection::establish(url)?;
+pub(crate) fn migrate(connection: &mut AnyConnection) -> Result<()> {
+    match connection {
+        AnyConnection::Postgresql(connection) => run_migrations(connection, MIGRATIONS),
+        #[cfg(feature = "sqlite")]
+        AnyConnection::Sqlite(connection) => run_migrations(connection, SQLITE_MIGRATIONS),
+    }
+    .map_err(|err| anyhow!(err.to_string()))
+}
+
+/// Connect to the database at `url`. The scheme of the URL selects
+/// the database: `postgres://` (or `postgresql://`) for PostgreSQL,
+/// and `sqlite://` followed by the path of the database file for
+/// SQLite, if enabled.
+fn establish(url: &str) -> ConnectionResult<AnyConnection> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        return Ok(AnyConnection::Postgresql(PgConnection::establish(url)?));
+    }
+
+    if let Some(path) = url.strip_prefix("sqlite://") {
+        #[cfg(feature = "sqlite")]
+        {
+            let mut connection = SqliteConnection::establish(path)?;
+            // foreign keys are needed for cascading deletes, and
+            // other connections of the pool may hold the lock briefly
+            connection
+                .batch_execute(
+                    "PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000; PRAGMA journal_mode = WAL;",
+                )
+                .map_err(ConnectionError::CouldntSetupConfiguration)?;
+
+            return Ok(AnyConnection::Sqlite(connection));
+        }
+
+        #[cfg(not(feature = "sqlite"))]
+        return Err(ConnectionError::InvalidConnectionUrl(format!(
+            "cannot open {path}: built without SQLite support, enable the `sqlite` feature"
+        )));
+    }
+
+    Err(ConnectionError::InvalidConnectionUrl(
+        "unsupported database URL, expected postgres:// or sqlite://".to_string(),
+    ))
+}
+
+pub(crate) fn connect(url: &str) -> Result<AnyConnection> {
+    let mut connection = establish(url)?;
 
-    run_migrations(&mut connection).map_err(|err| anyhow!(err.to_string()))?;
+    migrate(&mut connection)?;
 
     Ok(connection)
 }
+
+/// Hands out connections to the pool, picking the database by the
+/// scheme of the URL.
+#[derive(Debug)]
+pub(crate) struct ConnectionManager {
+    url: String,
+}
+
+impl r2d2::ManageConnection for ConnectionManager {
+    type Connection = AnyConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<AnyConnection, r2d2::Error> {
+        establish(&self.url).map_err(r2d2::Error::ConnectionError)
+    }
+
+    fn is_valid(&self, connection: &mut AnyConnection) -> Result<(), r2d2::Error> {
+        connection.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, connection: &mut AnyConnection) -> bool {
+        std::thread::panicking() || connection.is_broken()
+    }
+}
+
+/// A pool of connections to `url`. Connections are checked before
+/// they are handed out and re-established as needed, so that the pool
+/// survives restarts of the database. Doesn't connect right away.
+pub(crate) fn pool(url: &str) -> Pool {
+    Pool::builder()
+        .max_size(4)
+        .min_idle(Some(0))
+        .connection_timeout(Duration::from_secs(5))
+        .test_on_check_out(true)
+        .build_unchecked(ConnectionManager {
+            url: url.to_string(),
+        })
+}
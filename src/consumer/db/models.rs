@@ -10,6 +10,7 @@ use diesel::prelude::*;
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = super::schema::memes)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[cfg_attr(feature = "sqlite", diesel(check_for_backend(diesel::sqlite::Sqlite)))]
 pub(crate) struct Meme {
     pub(crate) id: i32,
     pub(crate) spoiler: bool,
@@ -18,7 +19,10 @@ pub(crate) struct Meme {
     pub(crate) account: String,
     pub(crate) channel: String,
     pub(crate) telegram_id: Option<i32>,
-    pub(crate) filename: String,
+    pub(crate) matrix_id: Option<String>,
+    pub(crate) telegram_chat_id: Option<i64>,
+    pub(crate) repost_of: Option<i32>,
+    pub(crate) telegram_chat_is_channel: Option<bool>,
 }
 
 #[derive(Insertable)]
@@ -30,5 +34,54 @@ pub(crate) struct NewMeme<'a> {
     pub(crate) account: &'a str,
     pub(crate) channel: &'a str,
     pub(crate) telegram_id: Option<i32>,
+    pub(crate) matrix_id: Option<&'a str>,
+    pub(crate) telegram_chat_id: Option<i64>,
+    pub(crate) telegram_chat_is_channel: Option<bool>,
+}
+
+#[allow(unused)]
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = super::schema::images)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[cfg_attr(feature = "sqlite", diesel(check_for_backend(diesel::sqlite::Sqlite)))]
+pub(crate) struct Image {
+    pub(crate) id: i32,
+    pub(crate) meme_id: i32,
+    pub(crate) position: i32,
+    pub(crate) filename: String,
+    pub(crate) telegram_id: Option<i32>,
+    pub(crate) matrix_id: Option<String>,
+    pub(crate) kind: String,
+    pub(crate) content_hash: Option<String>,
+    pub(crate) perceptual_hash: Option<i64>,
+    pub(crate) ocr_text: Option<String>,
+    pub(crate) mime_type: Option<String>,
+    pub(crate) width: Option<i32>,
+    pub(crate) height: Option<i32>,
+    pub(crate) size: Option<i64>,
+    pub(crate) hash_band_0: Option<i32>,
+    pub(crate) hash_band_1: Option<i32>,
+    pub(crate) hash_band_2: Option<i32>,
+    pub(crate) hash_band_3: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = super::schema::images)]
+pub(crate) struct NewImage<'a> {
+    pub(crate) meme_id: i32,
+    pub(crate) position: i32,
     pub(crate) filename: &'a str,
+    pub(crate) telegram_id: Option<i32>,
+    pub(crate) matrix_id: Option<&'a str>,
+    pub(crate) kind: &'a str,
+    pub(crate) content_hash: &'a str,
+    pub(crate) perceptual_hash: Option<i64>,
+    pub(crate) mime_type: &'a str,
+    pub(crate) width: Option<i32>,
+    pub(crate) height: Option<i32>,
+    pub(crate) size: i64,
+    pub(crate) hash_band_0: Option<i32>,
+    pub(crate) hash_band_1: Option<i32>,
+    pub(crate) hash_band_2: Option<i32>,
+    pub(crate) hash_band_3: Option<i32>,
 }
@@ -3,6 +3,43 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
+diesel::table! {
+    backfill_progress (chat_id) {
+        chat_id -> Int8,
+        message_id -> Int4,
+    }
+}
+
+diesel::table! {
+    images (id) {
+        id -> Int4,
+        meme_id -> Int4,
+        position -> Int4,
+        filename -> Text,
+        telegram_id -> Nullable<Int4>,
+        matrix_id -> Nullable<Text>,
+        kind -> Text,
+        content_hash -> Nullable<Text>,
+        perceptual_hash -> Nullable<Int8>,
+        ocr_text -> Nullable<Text>,
+        mime_type -> Nullable<Text>,
+        width -> Nullable<Int4>,
+        height -> Nullable<Int4>,
+        size -> Nullable<Int8>,
+        hash_band_0 -> Nullable<Int4>,
+        hash_band_1 -> Nullable<Int4>,
+        hash_band_2 -> Nullable<Int4>,
+        hash_band_3 -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    meme_tags (meme_id, tag_id) {
+        meme_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
 diesel::table! {
     memes (id) {
         id -> Int4,
@@ -12,6 +49,39 @@ diesel::table! {
         account -> Text,
         channel -> Text,
         telegram_id -> Nullable<Int4>,
-        filename -> Text,
+        matrix_id -> Nullable<Text>,
+        telegram_chat_id -> Nullable<Int8>,
+        repost_of -> Nullable<Int4>,
+        telegram_chat_is_channel -> Nullable<Bool>,
     }
 }
+
+diesel::table! {
+    reactions (image_id, emoji) {
+        image_id -> Int4,
+        emoji -> Text,
+        count -> Int4,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        name -> Text,
+    }
+}
+
+diesel::joinable!(images -> memes (meme_id));
+diesel::joinable!(meme_tags -> memes (meme_id));
+diesel::joinable!(meme_tags -> tags (tag_id));
+diesel::joinable!(reactions -> images (image_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    backfill_progress,
+    images,
+    meme_tags,
+    memes,
+    reactions,
+    tags,
+);
@@ -0,0 +1,235 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::sync::Arc;
+
+use anyhow::{Error, Result, anyhow, bail};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    MemeEvent, db, handle_event,
+    journal::{Entry, Journal},
+    media::Rejected,
+    thumbnails::Thumbnails,
+};
+use crate::{
+    config::{DatabaseConfiguration, ProcessingConfiguration, StorageConfiguration},
+    storage::{Backend, Quarantine},
+};
+
+/// An event that could not be handled, along with the reason.
+#[derive(Debug, Deserialize, Serialize)]
+struct DeadLetter<E = MemeEvent> {
+    failed_at: NaiveDateTime,
+    error: String,
+    event: E,
+}
+
+impl Entry for DeadLetter {
+    fn event_mut(&mut self) -> &mut MemeEvent {
+        &mut self.event
+    }
+}
+
+/// Events that failed repeatedly, kept in a local directory so that
+/// they can be replayed later, even if the database was unavailable.
+/// Payloads that can never be stored go into the quarantine instead.
+#[derive(Debug)]
+pub(super) struct DeadLetters {
+    journal: Journal,
+    quarantine: Quarantine,
+}
+
+impl DeadLetters {
+    pub(super) fn new(config: &StorageConfiguration) -> Self {
+        Self {
+            journal: Journal::new(&config.path().join(".dead-letters")),
+            quarantine: Quarantine::new(config),
+        }
+    }
+
+    /// Keep payloads that were left out of their events around for
+    /// inspection.
+    pub(super) async fn reject(&self, rejected: &[Rejected]) -> Result<()> {
+        for rejected in rejected {
+            log::error!("dropping image: {rejected}");
+            self.quarantine
+                .keep(&rejected.file, &rejected.data)
+                .await
+                .map_err(|err| anyhow!("failed to quarantine {}: {err}", rejected.file))?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) async fn store(&self, event: &MemeEvent, error: &Error) -> Result<()> {
+        let letter = DeadLetter {
+            failed_at: Utc::now().naive_utc(),
+            error: error.to_string(),
+            event,
+        };
+        let id = self.journal.append(&letter, event).await?;
+
+        log::warn!("stored dead letter {id}");
+        Ok(())
+    }
+}
+
+/// Try handling all dead letters once more, removing those that
+/// succeed.
+pub(crate) async fn replay(
+    storage: &StorageConfiguration,
+    processing: &ProcessingConfiguration,
+    database: &DatabaseConfiguration,
+) -> Result<()> {
+    let dead_letters = DeadLetters::new(storage);
+    let repost_threshold = processing.repost_threshold();
+    let thumbnails = Thumbnails::new(processing.thumbnails());
+    let storage = Backend::open(storage)?;
+    let pool = db::pool(database.url());
+
+    let mut failed = 0;
+    for id in dead_letters.journal.ids().await? {
+        let letter = dead_letters.journal.load::<DeadLetter>(&id).await?;
+        let event = Arc::new(letter.event);
+        log::info!(
+            "replaying dead letter {id}, which failed at {}: {}",
+            letter.failed_at,
+            letter.error
+        );
+
+        match handle_event(&storage, &thumbnails, &pool, repost_threshold, &event).await {
+            Ok(rejected) => {
+                dead_letters.reject(&rejected).await?;
+                dead_letters.journal.remove(&id).await?;
+            }
+            Err(err) => {
+                log::error!("dead letter {id} failed again: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} dead letters failed again");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+    use serde_json::json;
+    use test_log::test;
+
+    use super::{DeadLetter, DeadLetters};
+    use crate::{
+        config::StorageConfiguration,
+        consumer::{MemeEvent, Source, media::test::image},
+    };
+
+    fn storage(path: &std::path::Path) -> StorageConfiguration {
+        serde_json::from_value(json!({ "path": path })).expect("is a valid configuration")
+    }
+
+    #[test(tokio::test)]
+    async fn round_trip() {
+        let root = tempfile::tempdir().expect("can create directory");
+        let dead_letters = DeadLetters::new(&storage(root.path()));
+        let event = MemeEvent::album(vec![
+            (
+                image(b"first", "caption"),
+                Source::matrix(None, None, "$first"),
+            ),
+            (image(b"second", ""), Source::matrix(None, None, "$second")),
+        ]);
+
+        dead_letters
+            .store(&event, &anyhow!("database is down"))
+            .await
+            .expect("can store");
+
+        let ids = dead_letters.journal.ids().await.expect("can list");
+        assert_eq!(ids.len(), 1);
+
+        let letter = dead_letters
+            .journal
+            .load::<DeadLetter>(&ids[0])
+            .await
+            .expect("can load");
+        assert_eq!(letter.error, "database is down");
+        let images = letter.event.images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].data, b"first");
+        assert_eq!(images[0].text, "caption");
+        assert_eq!(images[1].data, b"second");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test(tokio::test)]
+    async fn replays() {
+        use diesel::prelude::*;
+
+        use crate::{
+            config::DatabaseConfiguration,
+            consumer::{
+                connection,
+                db::schema::memes,
+                media::test::{database, png},
+            },
+        };
+
+        let files = tempfile::tempdir().expect("can create directory");
+        let storage = storage(files.path());
+        let database = database().await;
+        let config: DatabaseConfiguration = serde_json::from_value(json!({ "url": &database.url }))
+            .expect("is a valid configuration");
+
+        let dead_letters = DeadLetters::new(&storage);
+        let meme = MemeEvent::new(
+            image(&png(4, 4), "caption"),
+            Source::matrix(None, None, "$meme"),
+        );
+        let broken = MemeEvent::new(
+            image(b"<html>not found</html>", ""),
+            Source::matrix(None, None, "$broken"),
+        );
+        for event in [&meme, &broken] {
+            dead_letters
+                .store(event, &anyhow!("database is down"))
+                .await
+                .expect("can store");
+        }
+
+        super::replay(&storage, &Default::default(), &config)
+            .await
+            .expect("can replay");
+
+        assert!(
+            dead_letters
+                .journal
+                .ids()
+                .await
+                .expect("can list")
+                .is_empty()
+        );
+        let count = memes::table
+            .count()
+            .get_result::<i64>(&mut *connection(&database.pool).await)
+            .expect("can count");
+        assert_eq!(count, 1);
+        assert!(
+            files
+                .path()
+                .join(".quarantine")
+                .read_dir()
+                .expect("can list")
+                .next()
+                .is_some()
+        );
+    }
+}
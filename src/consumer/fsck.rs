@@ -0,0 +1,303 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{collections::HashSet, fmt::Display};
+
+use anyhow::{Result, bail};
+use diesel::dsl::{delete, exists, not};
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use super::{
+    db::{self, AnyConnection, models::Image},
+    thumbnails,
+};
+use crate::{
+    config::{DatabaseConfiguration, StorageConfiguration},
+    storage::{Backend, Quarantine, Storage},
+};
+
+/// An inconsistency between the stored files and the `images` table.
+#[derive(Debug, PartialEq, Eq)]
+enum Problem {
+    /// A file that no image refers to, or a thumbnail of such a file.
+    Orphan { file: String },
+    /// An image whose file does not exist.
+    Missing { image: i32, meme: i32, file: String },
+    /// An image whose file is empty.
+    Empty { image: i32, meme: i32, file: String },
+    /// An image whose file does not match its content hash.
+    Mismatch { image: i32, meme: i32, file: String },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Orphan { file } => write!(f, "orphaned file {file}"),
+            Self::Missing { image, meme, file } => {
+                write!(f, "image {image} of meme {meme}: missing file {file}")
+            }
+            Self::Empty { image, meme, file } => {
+                write!(f, "image {image} of meme {meme}: empty file {file}")
+            }
+            Self::Mismatch { image, meme, file } => {
+                write!(f, "image {image} of meme {meme}: hash mismatch for {file}")
+            }
+        }
+    }
+}
+
+/// Compare the files in `storage` with the given images. Each file is
+/// read only once, even if it is shared between several images.
+/// Missing thumbnails are not a problem, `regen-thumbnails` takes
+/// care of those.
+async fn check(storage: &impl Storage, images: &[Image]) -> Result<Vec<Problem>> {
+    let files = storage.list().await?;
+    let known = files.iter().map(String::as_str).collect::<HashSet<_>>();
+    let by_file = images
+        .iter()
+        .into_group_map_by(|image| image.filename.as_str());
+
+    let mut problems = files
+        .iter()
+        .filter(|file| {
+            let original = thumbnails::original(file).unwrap_or(file);
+            !by_file.contains_key(original)
+        })
+        .map(|file| Problem::Orphan { file: file.clone() })
+        .collect::<Vec<_>>();
+
+    for (file, images) in by_file.into_iter().sorted_by_key(|(file, _)| *file) {
+        let data = if known.contains(file) {
+            Some(storage.get(file).await?)
+        } else {
+            None
+        };
+        let content_hash = data
+            .as_ref()
+            .map(|data| format!("{:x}", Sha256::digest(data)));
+
+        for image in images {
+            let (id, meme, file) = (image.id, image.meme_id, file.to_string());
+
+            match data {
+                None => problems.push(Problem::Missing {
+                    image: id,
+                    meme,
+                    file,
+                }),
+                Some(ref data) if data.is_empty() => problems.push(Problem::Empty {
+                    image: id,
+                    meme,
+                    file,
+                }),
+                // images stored before content hashing have no hash to compare
+                Some(_) if image.content_hash.is_some() && image.content_hash != content_hash => {
+                    problems.push(Problem::Mismatch {
+                        image: id,
+                        meme,
+                        file,
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Remove the images affected by `problems`, as well as memes that
+/// no longer have any images.
+fn remove_broken(db: &mut AnyConnection, problems: &[Problem]) -> Result<()> {
+    use db::schema::{images, memes};
+    use diesel::prelude::*;
+
+    let (ids, memes): (Vec<_>, Vec<_>) = problems
+        .iter()
+        .filter_map(|problem| match problem {
+            Problem::Orphan { .. } => None,
+            Problem::Missing { image, meme, .. }
+            | Problem::Empty { image, meme, .. }
+            | Problem::Mismatch { image, meme, .. } => Some((*image, *meme)),
+        })
+        .unzip();
+
+    db.transaction(|db| -> Result<()> {
+        let removed = delete(images::table.filter(images::id.eq_any(&ids))).execute(db)?;
+        log::info!("removed {removed} broken images");
+
+        let removed = delete(
+            memes::table
+                .filter(memes::id.eq_any(&memes))
+                .filter(not(exists(
+                    images::table.filter(images::meme_id.eq(memes::id)),
+                ))),
+        )
+        .execute(db)?;
+        log::info!("removed {removed} memes without images");
+
+        Ok(())
+    })
+}
+
+/// Check that the stored files and the database agree. With `repair`,
+/// move orphaned and corrupt files into the quarantine and remove
+/// images whose files are missing or corrupt.
+pub(crate) async fn fsck(
+    storage: &StorageConfiguration,
+    database: &DatabaseConfiguration,
+    repair: bool,
+) -> Result<()> {
+    use db::schema::images;
+    use diesel::prelude::*;
+
+    let quarantine = Quarantine::new(storage);
+    let storage = Backend::open(storage)?;
+    let mut db = db::connect(database.url())?;
+
+    let images = images::table
+        .select(Image::as_select())
+        .order(images::id)
+        .load::<Image>(&mut db)?;
+    let problems = check(&storage, &images).await?;
+
+    for problem in &problems {
+        log::warn!("{problem}");
+    }
+    log::info!(
+        "checked {} images, found {} problems",
+        images.len(),
+        problems.len()
+    );
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if !repair {
+        bail!(
+            "found {} problems, run with --repair to fix",
+            problems.len()
+        );
+    }
+
+    remove_broken(&mut db, &problems)?;
+
+    // orphans, and corrupt files that are no longer referenced now
+    let corrupt = problems
+        .iter()
+        .filter_map(|problem| match problem {
+            Problem::Orphan { file }
+            | Problem::Empty { file, .. }
+            | Problem::Mismatch { file, .. } => Some(file.as_str()),
+            Problem::Missing { .. } => None,
+        })
+        .unique()
+        .collect::<Vec<_>>();
+
+    for file in corrupt {
+        let references = images::table
+            .filter(images::filename.eq(file))
+            .count()
+            .get_result::<i64>(&mut db)?;
+
+        if references == 0 {
+            quarantine.take(&storage, file).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use sha2::{Digest, Sha256};
+    use test_log::test;
+
+    use super::{Problem, check};
+    use crate::{
+        consumer::db::models::Image,
+        storage::{LocalStorage, Storage},
+    };
+
+    fn image(id: i32, file: &str, data: &[u8]) -> Image {
+        Image {
+            id,
+            meme_id: id,
+            position: 0,
+            filename: file.to_string(),
+            telegram_id: Some(id),
+            matrix_id: None,
+            kind: "photo".to_string(),
+            content_hash: Some(format!("{:x}", Sha256::digest(data))),
+            perceptual_hash: None,
+            ocr_text: None,
+            mime_type: Some("image/png".to_string()),
+            width: None,
+            height: None,
+            size: Some(data.len() as i64),
+            hash_band_0: None,
+            hash_band_1: None,
+            hash_band_2: None,
+            hash_band_3: None,
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn finds_problems() {
+        let root = tempfile::tempdir().expect("can create directory");
+        let storage = LocalStorage::new(root.path());
+        storage.put("fine.png", b"fine").await.expect("can put");
+        storage
+            .put("thumbnails/320/fine.png.webp", b"thumbnail")
+            .await
+            .expect("can put");
+        storage.put("orphan.png", b"orphan").await.expect("can put");
+        storage
+            .put("thumbnails/320/orphan.png.webp", b"thumbnail")
+            .await
+            .expect("can put");
+        storage.put("empty.png", b"").await.expect("can put");
+        storage
+            .put("corrupt.png", b"corrupt")
+            .await
+            .expect("can put");
+
+        let images = [
+            image(1, "fine.png", b"fine"),
+            image(2, "fine.png", b"fine"),
+            image(3, "missing.png", b"missing"),
+            image(4, "empty.png", b"empty"),
+            image(5, "corrupt.png", b"intact"),
+        ];
+
+        assert_eq!(
+            check(&storage, &images).await.expect("can check"),
+            vec![
+                Problem::Orphan {
+                    file: "orphan.png".to_string()
+                },
+                Problem::Orphan {
+                    file: "thumbnails/320/orphan.png.webp".to_string()
+                },
+                Problem::Mismatch {
+                    image: 5,
+                    meme: 5,
+                    file: "corrupt.png".to_string()
+                },
+                Problem::Empty {
+                    image: 4,
+                    meme: 4,
+                    file: "empty.png".to_string()
+                },
+                Problem::Missing {
+                    image: 3,
+                    meme: 3,
+                    file: "missing.png".to_string()
+                },
+            ]
+        );
+    }
+}
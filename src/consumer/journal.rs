@@ -0,0 +1,183 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{fs, io::AsyncWriteExt};
+
+use super::{MemeEvent, extension};
+
+const ENTRY_FILE: &str = "entry.json";
+
+/// Something that can be read from a [`Journal`].
+pub(super) trait Entry: DeserializeOwned {
+    fn event_mut(&mut self) -> &mut MemeEvent;
+}
+
+impl Entry for MemeEvent {
+    fn event_mut(&mut self) -> &mut MemeEvent {
+        self
+    }
+}
+
+/// Events kept in a local directory. Each entry is a subdirectory
+/// holding the entry as JSON and the data of its images in separate
+/// files. Entries are named such that they sort by creation time.
+#[derive(Debug)]
+pub(super) struct Journal {
+    path: PathBuf,
+}
+
+fn image_file(position: usize, mime_type: &str) -> String {
+    format!("{position}.{}", extension(mime_type))
+}
+
+/// Write `data` to the file `path`, and wait until it is on disk.
+async fn write_synced(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = fs::File::create(path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Wait until the entries of the directory `path` are on disk.
+async fn sync_directory(path: &Path) -> Result<()> {
+    Ok(fs::File::open(path).await?.sync_all().await?)
+}
+
+impl Journal {
+    pub(super) fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Add `entry` holding `event`, returning its id.
+    pub(super) async fn append(&self, entry: &impl Serialize, event: &MemeEvent) -> Result<String> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let id = format!(
+            "{}-{:06}",
+            Utc::now().format("%Y%m%dT%H%M%S%.6f"),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+
+        // fill a hidden directory first, so that readers never see a
+        // partially written entry, not even after a crash
+        let temporary = self.path.join(format!(".{id}"));
+        fs::create_dir_all(&temporary).await?;
+        for (position, image) in event.images().into_iter().enumerate() {
+            write_synced(
+                &temporary.join(image_file(position, &image.mime_type)),
+                &image.data,
+            )
+            .await?;
+        }
+        write_synced(
+            &temporary.join(ENTRY_FILE),
+            &serde_json::to_vec_pretty(entry)?,
+        )
+        .await?;
+        sync_directory(&temporary).await?;
+        fs::rename(&temporary, self.path.join(&id)).await?;
+        sync_directory(&self.path).await?;
+
+        Ok(id)
+    }
+
+    /// The ids of all entries, oldest first.
+    pub(super) async fn ids(&self) -> Result<Vec<String>> {
+        if !fs::try_exists(&self.path).await? {
+            return Ok(Vec::new());
+        }
+
+        let mut ids = Vec::new();
+        let mut entries = fs::read_dir(&self.path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with('.') {
+                ids.push(name);
+            }
+        }
+
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub(super) async fn load<E: Entry>(&self, id: &str) -> Result<E> {
+        let path = self.path.join(id);
+        let mut entry: E = serde_json::from_slice(&fs::read(path.join(ENTRY_FILE)).await?)
+            .with_context(|| format!("invalid entry {id} in {:?}", self.path))?;
+
+        for (position, image) in entry.event_mut().images_mut().into_iter().enumerate() {
+            image.data = fs::read(path.join(image_file(position, &image.mime_type))).await?;
+        }
+
+        Ok(entry)
+    }
+
+    pub(super) async fn remove(&self, id: &str) -> Result<()> {
+        Ok(fs::remove_dir_all(self.path.join(id)).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+    use test_log::test;
+
+    use super::Journal;
+    use crate::consumer::{MediaKind, MemeEvent, MemeImage, Source};
+
+    #[test(tokio::test)]
+    async fn round_trip() {
+        let root = tempfile::tempdir().expect("can create directory");
+        let journal = Journal::new(root.path());
+        let image = |data: &[u8], text: &str| {
+            MemeImage::new(
+                data.to_vec(),
+                MediaKind::Photo,
+                "image/png".to_string(),
+                false,
+                text.to_string(),
+                NaiveDateTime::default(),
+            )
+        };
+
+        let album = MemeEvent::album(vec![
+            (
+                image(b"first", "caption"),
+                Source::matrix(None, None, "$first"),
+            ),
+            (image(b"second", ""), Source::matrix(None, None, "$second")),
+        ]);
+        let first = journal.append(&album, &album).await.expect("can append");
+        let deletion = MemeEvent::delete(Source::matrix(None, None, "$first"));
+        let second = journal
+            .append(&deletion, &deletion)
+            .await
+            .expect("can append");
+        assert_eq!(
+            journal.ids().await.expect("can list"),
+            vec![first.clone(), second]
+        );
+
+        let event = journal.load::<MemeEvent>(&first).await.expect("can load");
+        let images = event.images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].data, b"first");
+        assert_eq!(images[0].text, "caption");
+        assert_eq!(images[1].data, b"second");
+
+        journal.remove(&first).await.expect("can remove");
+        assert_eq!(journal.ids().await.expect("can list").len(), 1);
+    }
+}
@@ -0,0 +1,245 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{fmt::Display, io::Read};
+
+use flate2::read::GzDecoder;
+use image::DynamicImage;
+use serde_json::Value;
+
+/// How much of an animated sticker we unpack at most, far more than
+/// Telegram allows.
+const MAX_STICKER_SIZE: u64 = 16 << 20;
+
+/// What a file contains, going by its contents rather than by what
+/// the sender claimed.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct Media {
+    pub(super) mime_type: &'static str,
+    /// only known for images we can decode
+    pub(super) width: Option<i32>,
+    pub(super) height: Option<i32>,
+}
+
+/// ISO base media files (MP4, QuickTime, HEIF) name their brand
+/// right after the `ftyp` box header.
+fn iso_brand(data: &[u8]) -> Option<&[u8]> {
+    (data.get(4..8)? == b"ftyp").then_some(data.get(8..12)?)
+}
+
+/// Animated stickers are gzipped Lottie files, i.e., JSON objects
+/// with a version and some layers.
+fn is_sticker(data: &[u8]) -> bool {
+    if !data.starts_with(&[0x1f, 0x8b]) {
+        return false;
+    }
+
+    let mut json = Vec::new();
+    let unpacked = GzDecoder::new(data)
+        .take(MAX_STICKER_SIZE)
+        .read_to_end(&mut json);
+    if unpacked.is_err() {
+        return false;
+    }
+
+    serde_json::from_slice::<Value>(&json).is_ok_and(|lottie| {
+        lottie.get("v").is_some_and(Value::is_string)
+            && lottie.get("layers").is_some_and(Value::is_array)
+    })
+}
+
+/// Find out what `data` is, along with the decoded image if it is
+/// one. Images must decode completely, which rules out image formats
+/// we can't decode at all; other media, including HEIC images, are
+/// recognised by their signature. Anything else is `None`. Decoding
+/// is CPU-bound, so call this on a blocking thread.
+pub(super) fn sniff(data: &[u8]) -> Option<(Media, Option<DynamicImage>)> {
+    if let Ok(format) = image::guess_format(data) {
+        if !format.reading_enabled() {
+            return None;
+        }
+
+        let image = image::load_from_memory_with_format(data, format).ok()?;
+        let media = Media {
+            mime_type: format.to_mime_type(),
+            width: image.width().try_into().ok(),
+            height: image.height().try_into().ok(),
+        };
+        return Some((media, Some(image)));
+    }
+
+    let mime_type = match iso_brand(data) {
+        Some(b"heic" | b"heix" | b"heim" | b"heis" | b"mif1" | b"msf1") => "image/heic",
+        Some(b"qt  ") => "video/quicktime",
+        Some(
+            b"isom" | b"iso2" | b"iso4" | b"iso5" | b"iso6" | b"mp41" | b"mp42" | b"avc1" | b"dash"
+            | b"M4V " | b"mmp4",
+        ) => "video/mp4",
+        Some(_) => return None,
+        None if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) => "video/webm",
+        None if is_sticker(data) => "application/x-tgsticker",
+        None => return None,
+    };
+
+    let media = Media {
+        mime_type,
+        width: None,
+        height: None,
+    };
+    Some((media, None))
+}
+
+/// A payload that is neither a decodable image nor any other kind of
+/// media we know. Handling it again won't help, so it is quarantined
+/// under `file` instead.
+#[derive(Debug)]
+pub(super) struct Rejected {
+    pub(super) file: String,
+    pub(super) data: Vec<u8>,
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is neither a decodable image nor any other known kind of media",
+            self.file
+        )
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::io::{Cursor, Write};
+
+    use chrono::NaiveDateTime;
+    use flate2::{Compression, write::GzEncoder};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use test_log::test;
+
+    use super::{Media, sniff};
+    use crate::consumer::{MediaKind, MemeImage};
+
+    /// A black PNG image of the given size.
+    pub(crate) fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut data, ImageFormat::Png)
+            .expect("can encode");
+        data.into_inner()
+    }
+
+    /// A photo holding `data`, captioned with `text`.
+    pub(crate) fn image(data: &[u8], text: &str) -> MemeImage {
+        MemeImage::new(
+            data.to_vec(),
+            MediaKind::Photo,
+            "image/png".to_string(),
+            false,
+            text.to_string(),
+            NaiveDateTime::default(),
+        )
+    }
+
+    /// A migrated SQLite database, removed when dropped.
+    #[cfg(feature = "sqlite")]
+    pub(crate) struct Database {
+        pub(crate) url: String,
+        pub(crate) pool: crate::consumer::db::Pool,
+        _dir: tempfile::TempDir,
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) async fn database() -> Database {
+        use crate::consumer::{connection, db};
+
+        let dir = tempfile::tempdir().expect("can create directory");
+        let url = format!("sqlite://{}", dir.path().join("memes.db").display());
+        let pool = db::pool(&url);
+        db::migrate(&mut *connection(&pool).await).expect("can migrate");
+
+        Database {
+            url,
+            pool,
+            _dir: dir,
+        }
+    }
+
+    fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut data, format)
+            .expect("can encode");
+        data.into_inner()
+    }
+
+    #[test]
+    fn sniffs_images() {
+        let (media, image) = sniff(&png(40, 30)).expect("is an image");
+        assert_eq!(
+            media,
+            Media {
+                mime_type: "image/png",
+                width: Some(40),
+                height: Some(30),
+            }
+        );
+        assert_eq!(image.map(|image| image.width()), Some(40));
+
+        for (format, mime_type) in [
+            (ImageFormat::Bmp, "image/bmp"),
+            (ImageFormat::Tiff, "image/tiff"),
+        ] {
+            assert_eq!(
+                sniff(&encode(8, 6, format)).map(|(media, _)| media),
+                Some(Media {
+                    mime_type,
+                    width: Some(8),
+                    height: Some(6),
+                })
+            );
+        }
+
+        let mut truncated = png(40, 30);
+        truncated.truncate(truncated.len() / 2);
+        assert!(sniff(&truncated).is_none());
+
+        // we can't decode icons
+        assert!(sniff(b"\0\0\x01\0\x01\0\x10\x10").is_none());
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).expect("can compress");
+        encoder.finish().expect("can compress")
+    }
+
+    #[test]
+    fn sniffs_other_media() {
+        let media = |data: &[u8]| sniff(data).map(|(media, _)| media.mime_type);
+
+        assert_eq!(media(b"\0\0\0\x20ftypisom\0\0\x02\0"), Some("video/mp4"));
+        assert_eq!(
+            media(b"\0\0\0\x14ftypqt  \0\0\0\0"),
+            Some("video/quicktime")
+        );
+        assert_eq!(media(b"\0\0\0\x18ftypheic\0\0\0\0"), Some("image/heic"));
+        assert_eq!(media(b"\0\0\0\x14ftypmeme\0\0\0\0"), None);
+        assert_eq!(
+            media(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81"),
+            Some("video/webm")
+        );
+        assert_eq!(
+            media(&gzip(br#"{"v": "5.5.2", "fr": 60, "layers": []}"#)),
+            Some("application/x-tgsticker")
+        );
+        assert_eq!(media(&gzip(b"<html>not a meme</html>")), None);
+        assert_eq!(media(b"\x1f\x8b\x08\0"), None);
+        assert_eq!(media(b"<html>not a meme</html>"), None);
+        assert_eq!(media(b""), None);
+    }
+}
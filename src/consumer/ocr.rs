@@ -0,0 +1,340 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{collections::HashSet, path::PathBuf, process::Stdio, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use diesel::dsl::{max, update};
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+    sync::mpsc::{self, Sender},
+    task::JoinHandle,
+};
+
+use super::{Stopping, connection, db, with_db, with_db_until};
+use crate::{
+    config::{
+        DatabaseConfiguration, OcrConfiguration, ProcessingConfiguration, StorageConfiguration,
+    },
+    storage::{Backend, Storage},
+};
+
+/// How many files to look up at once.
+const BATCH_SIZE: i64 = 16;
+/// Files the engine can read; all others are marked as having no text.
+const EXTENSIONS: [&str; 4] = ["jpg", "png", "gif", "webp"];
+
+/// Recognises text in images by running a local OCR engine, so that
+/// no image ever leaves the machine.
+#[derive(Debug)]
+pub(super) struct Ocr {
+    command: PathBuf,
+    languages: String,
+}
+
+impl Ocr {
+    pub(super) fn new(config: &OcrConfiguration) -> Self {
+        Self {
+            command: config.command().to_path_buf(),
+            languages: config.languages().to_string(),
+        }
+    }
+
+    /// Make sure that the engine can be run at all.
+    async fn check(&self) -> Result<()> {
+        Command::new(&self.command)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .with_context(|| format!("failed to run OCR engine {:?}", self.command))?;
+
+        Ok(())
+    }
+
+    /// The text in the image `data`. Fails if the engine can't read
+    /// the image, so that it isn't mistaken for one without text.
+    async fn recognize(&self, data: &[u8]) -> Result<String> {
+        let mut child = Command::new(&self.command)
+            .args(["stdin", "stdout", "-l", &self.languages])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("failed to run OCR engine {:?}", self.command))?;
+
+        // write concurrently, so that a full stdout pipe can't block us
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let data = data.to_vec();
+        let writer = tokio::spawn(async move { stdin.write_all(&data).await });
+        let output = child.wait_with_output().await?;
+        if let Ok(Err(err)) = writer.await {
+            log::debug!("OCR engine didn't read the whole image: {err}");
+        }
+
+        if !output.status.success() {
+            bail!(
+                "OCR engine failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// Recognise the text of `file` and store it for all images
+    /// showing that file. Reuses the text of identical images.
+    async fn index(
+        &self,
+        storage: &Backend,
+        pool: &db::Pool,
+        stopping: &Stopping,
+        file: &str,
+    ) -> Result<()> {
+        use db::schema::images;
+        use diesel::prelude::*;
+
+        let known = with_db_until(pool, stopping, {
+            let file = file.to_string();
+            move |db| {
+                Ok(images::table
+                    .filter(images::filename.eq(file))
+                    .filter(images::ocr_text.is_not_null())
+                    .select(images::ocr_text)
+                    .first::<Option<String>>(db)
+                    .optional()?
+                    .flatten())
+            }
+        })
+        .await?;
+
+        let text = match known {
+            Some(text) => text,
+            None if EXTENSIONS.iter().any(|extension| file.ends_with(extension)) => {
+                self.recognize(&storage.get(file).await?).await?
+            }
+            None => String::new(),
+        };
+        log::debug!("recognised {} characters in {file}", text.len());
+
+        with_db_until(pool, stopping, {
+            let file = file.to_string();
+            move |db| {
+                update(
+                    images::table
+                        .filter(images::filename.eq(file))
+                        .filter(images::ocr_text.is_null()),
+                )
+                .set(images::ocr_text.eq(text))
+                .execute(db)?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Process all images without text, newest first, until there are
+    /// none left or `stop` says so. Returns the number of files
+    /// indexed and skipped.
+    async fn index_pending(
+        &self,
+        storage: &Backend,
+        pool: &db::Pool,
+        stopping: &Stopping,
+        stop: impl Fn() -> bool,
+    ) -> Result<(usize, usize)> {
+        use db::schema::images;
+        use diesel::prelude::*;
+
+        // files that can't be read right now are retried next time
+        let mut skipped = HashSet::new();
+        let mut indexed = 0;
+
+        while !stop() {
+            let excluded = skipped.iter().cloned().collect::<Vec<String>>();
+            let pending = with_db_until(pool, stopping, move |db| {
+                Ok(images::table
+                    .filter(images::ocr_text.is_null())
+                    .filter(images::filename.ne_all(excluded))
+                    .group_by(images::filename)
+                    .select(images::filename)
+                    .order(max(images::id).desc())
+                    .limit(BATCH_SIZE)
+                    .load::<String>(db)?)
+            })
+            .await?;
+
+            if pending.is_empty() {
+                break;
+            }
+
+            for file in pending {
+                if stop() {
+                    break;
+                }
+
+                match self.index(storage, pool, stopping, &file).await {
+                    Ok(()) => indexed += 1,
+                    Err(err) => {
+                        log::warn!("skipping OCR of {file}: {err}");
+                        skipped.insert(file);
+                    }
+                }
+            }
+        }
+
+        Ok((indexed, skipped.len()))
+    }
+}
+
+/// Recognises text in the background, so that ingestion never waits
+/// for the OCR engine.
+#[derive(Debug)]
+pub(super) struct Worker {
+    wake: Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Worker {
+    pub(super) fn spawn(
+        ocr: Ocr,
+        storage: Arc<Backend>,
+        pool: db::Pool,
+        stopping: Stopping,
+    ) -> Self {
+        let (wake, mut woken) = mpsc::channel(1);
+        let task = tokio::spawn(async move {
+            if let Err(err) = ocr.check().await {
+                log::error!("not recognising text in images: {err}");
+                return;
+            }
+
+            while woken.recv().await.is_some() {
+                match ocr
+                    .index_pending(&storage, &pool, &stopping, || woken.is_closed())
+                    .await
+                {
+                    Ok((0, 0)) => {}
+                    Ok((indexed, skipped)) => {
+                        log::info!("recognised text in {indexed} files, skipped {skipped}")
+                    }
+                    Err(err) => log::error!("failed to recognise text in images: {err}"),
+                }
+            }
+        });
+
+        // catch up on images stored while OCR was disabled
+        _ = wake.try_send(());
+
+        Self { wake, task }
+    }
+
+    /// Look for new images. Never blocks.
+    pub(super) fn wake(&self) {
+        _ = self.wake.try_send(());
+    }
+
+    /// Finish the current image and stop.
+    pub(super) async fn stop(self) {
+        drop(self.wake);
+        _ = self.task.await;
+    }
+}
+
+/// Recognise text in all images that don't have any yet, or, with
+/// `all`, in every image.
+pub(crate) async fn reindex_ocr(
+    storage: &StorageConfiguration,
+    processing: &ProcessingConfiguration,
+    database: &DatabaseConfiguration,
+    all: bool,
+) -> Result<()> {
+    use db::schema::images;
+    use diesel::prelude::*;
+
+    let Some(config) = processing.ocr() else {
+        bail!("OCR is not enabled, add a [processing.ocr] section to the configuration");
+    };
+    let ocr = Ocr::new(config);
+    ocr.check().await?;
+    let storage = Backend::open(storage)?;
+    let pool = db::pool(database.url());
+    db::migrate(&mut *connection(&pool).await)?;
+
+    if all {
+        let reset = with_db(&pool, |db| {
+            Ok(update(images::table)
+                .set(images::ocr_text.eq(None::<String>))
+                .execute(db)?)
+        })
+        .await?;
+        log::info!("cleared the text of {reset} images");
+    }
+
+    let (indexed, skipped) = ocr
+        .index_pending(&storage, &pool, &Stopping::never(), || false)
+        .await?;
+    log::info!("recognised text in {indexed} files, skipped {skipped}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+    use test_log::test;
+
+    use super::Ocr;
+
+    fn engine(dir: &Path, script: &str) -> Ocr {
+        let command = dir.join("ocr");
+        fs::write(&command, format!("#!/bin/sh\n{script}\n")).expect("can write engine");
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755))
+            .expect("can make engine executable");
+
+        Ocr {
+            command,
+            languages: "deu+eng".to_string(),
+        }
+    }
+
+    #[test(tokio::test)]
+    async fn recognizes_text() {
+        let dir = tempfile::tempdir().expect("can create directory");
+        let ocr = engine(dir.path(), r#"cat > /dev/null; echo "  Text in $4  ""#);
+
+        ocr.check().await.expect("engine runs");
+        assert_eq!(
+            ocr.recognize(b"image").await.expect("engine runs"),
+            "Text in deu+eng"
+        );
+    }
+
+    #[test(tokio::test)]
+    async fn unreadable_images_fail() {
+        let dir = tempfile::tempdir().expect("can create directory");
+        let ocr = engine(dir.path(), "echo garbage; echo unreadable >&2; exit 1");
+
+        let err = ocr.recognize(b"image").await.expect_err("engine fails");
+        assert!(err.to_string().ends_with("unreadable"));
+    }
+
+    #[test(tokio::test)]
+    async fn missing_engine() {
+        let dir = tempfile::tempdir().expect("can create directory");
+        let ocr = Ocr {
+            command: dir.path().join("missing"),
+            languages: "eng".to_string(),
+        };
+
+        assert!(ocr.check().await.is_err());
+        assert!(ocr.recognize(b"image").await.is_err());
+    }
+}
@@ -0,0 +1,163 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::dsl::{delete, insert_into, update};
+
+use super::{
+    Reaction,
+    db::{
+        self, AnyConnection,
+        models::{Image, Meme},
+    },
+};
+
+/// Replace the reactions to the messages of `images` with
+/// `reactions`. Reactions are kept per message, so that the
+/// reactions to different messages of an album add up.
+pub(super) fn record(
+    db: &mut AnyConnection,
+    images: &[Image],
+    reactions: &[Reaction],
+    timestamp: NaiveDateTime,
+) -> Result<()> {
+    use db::schema::reactions;
+    use diesel::prelude::*;
+
+    let reactions = reactions
+        .iter()
+        .filter(|reaction| reaction.count > 0)
+        .collect::<Vec<_>>();
+    let emoji = reactions
+        .iter()
+        .map(|reaction| reaction.emoji.as_str())
+        .collect::<Vec<_>>();
+
+    for image in images {
+        delete(
+            reactions::table
+                .filter(reactions::image_id.eq(image.id))
+                .filter(reactions::emoji.ne_all(&emoji)),
+        )
+        .execute(db)?;
+
+        for reaction in &reactions {
+            let updated = update(reactions::table.find((image.id, &reaction.emoji)))
+                .set((
+                    reactions::count.eq(reaction.count),
+                    reactions::updated_at.eq(timestamp),
+                ))
+                .execute(db)?;
+
+            if updated == 0 {
+                insert_into(reactions::table)
+                    .values((
+                        reactions::image_id.eq(image.id),
+                        reactions::emoji.eq(&reaction.emoji),
+                        reactions::count.eq(reaction.count),
+                        reactions::updated_at.eq(timestamp),
+                    ))
+                    .execute(db)?;
+            }
+        }
+
+        log::debug!(
+            "recorded {} reactions to image {} of meme {}",
+            reactions.len(),
+            image.id,
+            image.meme_id
+        );
+    }
+
+    Ok(())
+}
+
+/// Change the reactions to the messages of `images` by `changes`,
+/// e.g., when a single user changed their reactions. Reactions whose
+/// count drops to zero are removed.
+pub(super) fn adjust(
+    db: &mut AnyConnection,
+    images: &[Image],
+    changes: &[Reaction],
+    timestamp: NaiveDateTime,
+) -> Result<()> {
+    use db::schema::reactions;
+    use diesel::prelude::*;
+
+    for image in images {
+        for change in changes.iter().filter(|change| change.count != 0) {
+            let updated = update(reactions::table.find((image.id, &change.emoji)))
+                .set((
+                    reactions::count.eq(reactions::count + change.count),
+                    reactions::updated_at.eq(timestamp),
+                ))
+                .execute(db)?;
+
+            if updated == 0 && change.count > 0 {
+                insert_into(reactions::table)
+                    .values((
+                        reactions::image_id.eq(image.id),
+                        reactions::emoji.eq(&change.emoji),
+                        reactions::count.eq(change.count),
+                        reactions::updated_at.eq(timestamp),
+                    ))
+                    .execute(db)?;
+            }
+        }
+
+        delete(
+            reactions::table
+                .filter(reactions::image_id.eq(image.id))
+                .filter(reactions::count.le(0)),
+        )
+        .execute(db)?;
+    }
+
+    Ok(())
+}
+
+/// The reactions to meme `meme`, summed over all of its images, most
+/// frequent first.
+pub(crate) fn of_meme(db: &mut AnyConnection, meme: i32) -> Result<Vec<(String, i64)>> {
+    use db::schema::{images, reactions};
+    use diesel::{dsl::sum, prelude::*};
+
+    Ok(reactions::table
+        .inner_join(images::table)
+        .filter(images::meme_id.eq(meme))
+        .group_by(reactions::emoji)
+        .select((reactions::emoji, sum(reactions::count)))
+        .order((sum(reactions::count).desc(), reactions::emoji))
+        .load::<(String, Option<i64>)>(db)?
+        .into_iter()
+        .map(|(emoji, count)| (emoji, count.unwrap_or_default()))
+        .collect())
+}
+
+/// The `limit` memes posted during `period` with the most reactions,
+/// along with their total number of reactions.
+pub(crate) fn best_of(
+    db: &mut AnyConnection,
+    period: RangeInclusive<NaiveDateTime>,
+    limit: i64,
+) -> Result<Vec<(Meme, i64)>> {
+    use db::schema::{images, memes, reactions};
+    use diesel::{dsl::sum, prelude::*};
+
+    Ok(memes::table
+        .inner_join(images::table.inner_join(reactions::table))
+        .filter(memes::timestamp.between(*period.start(), *period.end()))
+        .group_by(memes::id)
+        .select((Meme::as_select(), sum(reactions::count)))
+        .order((sum(reactions::count).desc(), memes::timestamp))
+        .limit(limit)
+        .load::<(Meme, Option<i64>)>(db)?
+        .into_iter()
+        .map(|(meme, count)| (meme, count.unwrap_or_default()))
+        .collect())
+}
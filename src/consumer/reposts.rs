@@ -0,0 +1,225 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use anyhow::Result;
+use diesel::dsl::update;
+use image::{DynamicImage, imageops::FilterType};
+
+use super::db::{self, AnyConnection, models::Meme};
+
+/// Perceptual hashes are indexed in this many bands of equal width.
+/// Two hashes within distance `d` of each other differ in at most
+/// `d / BANDS` bits of some band.
+const BANDS: usize = 4;
+const BAND_BITS: u32 = 64 / BANDS as u32;
+/// Beyond this, too many bands are similar to look them all up, and
+/// every image is a candidate.
+const MAX_BAND_DISTANCE: u32 = 2;
+
+/// Compute the difference hash (dHash) of an image: scale it down to
+/// 9×8 grayscale pixels and record for each pixel whether it is
+/// brighter than its right neighbour.
+pub(super) fn perceptual_hash(image: &DynamicImage) -> i64 {
+    let image = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if image.get_pixel(x, y)[0] > image.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash as i64
+}
+
+fn distance(left: i64, right: i64) -> u32 {
+    (left ^ right).count_ones()
+}
+
+/// Split a perceptual hash into its bands, most significant first.
+/// Images without a hash have no bands.
+pub(super) fn bands(hash: Option<i64>) -> [Option<i32>; BANDS] {
+    std::array::from_fn(|band| {
+        let shift = BAND_BITS * (BANDS - 1 - band) as u32;
+        hash.map(|hash| ((hash as u64 >> shift) & ((1 << BAND_BITS) - 1)) as i32)
+    })
+}
+
+/// All bands that differ from `band` in at most `distance` bits.
+fn similar_bands(band: i32, distance: u32) -> Vec<i32> {
+    (0..1 << BAND_BITS)
+        .filter(|candidate| (candidate ^ band).count_ones() <= distance)
+        .collect()
+}
+
+/// The memes with an image whose perceptual hash might be within
+/// `threshold` of `hash`, along with that hash. Only looks at images
+/// sharing a similar band with `hash`, unless `threshold` is too
+/// large for that.
+fn candidates(
+    db: &mut AnyConnection,
+    meme: i32,
+    hash: i64,
+    threshold: u32,
+) -> Result<Vec<(i32, Option<i32>, Option<i64>)>> {
+    use db::schema::{images, memes};
+    use diesel::prelude::*;
+
+    let query = images::table
+        .inner_join(memes::table)
+        .select((memes::id, memes::repost_of, images::perceptual_hash))
+        .filter(memes::id.ne(meme))
+        .filter(images::perceptual_hash.is_not_null())
+        .into_boxed();
+
+    let band_distance = threshold / BANDS as u32;
+    if band_distance > MAX_BAND_DISTANCE {
+        return Ok(query.load(db)?);
+    }
+
+    let [first, second, third, fourth] =
+        bands(Some(hash)).map(|band| similar_bands(band.expect("hash is present"), band_distance));
+    Ok(query
+        .filter(
+            images::hash_band_0
+                .eq_any(first)
+                .or(images::hash_band_1.eq_any(second))
+                .or(images::hash_band_2.eq_any(third))
+                .or(images::hash_band_3.eq_any(fourth)),
+        )
+        .load(db)?)
+}
+
+/// Mark the meme `meme` as a repost if any of the given perceptual
+/// hashes is within `threshold` of an image of some other meme.
+/// Reposts always link to the original, never to another repost.
+pub(super) fn detect(
+    db: &mut AnyConnection,
+    meme: i32,
+    hashes: &[i64],
+    threshold: u32,
+) -> Result<()> {
+    use db::schema::memes;
+    use diesel::prelude::*;
+
+    // the oldest similar meme wins, as if all hashes were compared
+    // at once
+    let mut similar = Vec::new();
+    for hash in hashes {
+        similar.extend(
+            candidates(db, meme, *hash, threshold)?
+                .into_iter()
+                .filter(|(_, _, candidate)| {
+                    candidate.is_some_and(|candidate| distance(*hash, candidate) <= threshold)
+                })
+                .map(|(id, repost_of, _)| (id, repost_of.unwrap_or(id))),
+        );
+    }
+    let original = similar
+        .into_iter()
+        .min_by_key(|(id, _)| *id)
+        .map(|(_, original)| original);
+
+    if let Some(original) = original {
+        log::info!("meme {meme} is a repost of meme {original}");
+        update(memes::table.find(meme))
+            .set(memes::repost_of.eq(original))
+            .execute(db)?;
+    }
+
+    Ok(())
+}
+
+/// All variants of meme `meme`: the original and all of its reposts,
+/// oldest first. Empty if there is no such meme.
+pub(super) fn variants(db: &mut AnyConnection, meme: i32) -> Result<Vec<Meme>> {
+    use db::schema::memes;
+    use diesel::prelude::*;
+
+    let Some(repost_of) = memes::table
+        .find(meme)
+        .select(memes::repost_of)
+        .first::<Option<i32>>(db)
+        .optional()?
+    else {
+        return Ok(Vec::new());
+    };
+    let original = repost_of.unwrap_or(meme);
+
+    Ok(memes::table
+        .filter(memes::id.eq(original).or(memes::repost_of.eq(original)))
+        .order((memes::timestamp, memes::id))
+        .select(Meme::as_select())
+        .load(db)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        f64::consts::{PI, TAU},
+        io::Cursor,
+    };
+
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+    use test_log::test;
+
+    use super::{BANDS, bands, distance, perceptual_hash, similar_bands};
+
+    /// `image` as it looks after a round trip through `format`.
+    fn encode(image: &RgbImage, format: ImageFormat) -> DynamicImage {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).expect("can encode");
+        image::load_from_memory(data.get_ref()).expect("can decode")
+    }
+
+    fn waves(width: u32, height: u32, invert: bool) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let x = f64::from(x) / f64::from(width);
+            let y = f64::from(y) / f64::from(height);
+            let value = 127.0 + 64.0 * (x * TAU).sin() + 63.0 * (y * PI).cos();
+            let value = if invert { 255.0 - value } else { value } as u8;
+            Rgb([value, value, value])
+        })
+    }
+
+    #[test]
+    fn resized_images_are_similar() {
+        let large = perceptual_hash(&encode(&waves(640, 480, false), ImageFormat::Png));
+        let small = perceptual_hash(&encode(&waves(320, 240, false), ImageFormat::Jpeg));
+
+        assert!(distance(large, small) <= 4);
+    }
+
+    #[test]
+    fn different_images_are_not_similar() {
+        let image = perceptual_hash(&encode(&waves(640, 480, false), ImageFormat::Png));
+        let inverted = perceptual_hash(&encode(&waves(640, 480, true), ImageFormat::Png));
+
+        assert!(distance(image, inverted) > 32);
+    }
+
+    #[test]
+    fn similar_hashes_share_a_similar_band() {
+        let hash = 0x0123_4567_89ab_cdef;
+        let other = hash ^ 0b111 ^ (0b11 << 20) ^ (0b111 << 40);
+        let threshold = 8;
+        assert_eq!(distance(hash, other), threshold);
+
+        let similar = bands(Some(hash))
+            .map(|band| similar_bands(band.expect("has bands"), threshold / BANDS as u32));
+        assert!(
+            bands(Some(other))
+                .iter()
+                .zip(&similar)
+                .any(|(band, similar)| similar.contains(&band.expect("has bands")))
+        );
+
+        assert_eq!(bands(Some(-1)), [Some(0xffff); BANDS]);
+        assert_eq!(bands(None), [None; BANDS]);
+    }
+}
@@ -0,0 +1,290 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+use diesel::{
+    dsl::sql,
+    expression::BoxableExpression,
+    sql_types::{Bool, Float, Text},
+};
+
+use super::{
+    db::{self, AnyConnection, MultiBackend, models::Meme},
+    tags,
+};
+use crate::config::DatabaseConfiguration;
+
+/// What to search for. Empty filters match every meme.
+#[derive(Debug, Default)]
+pub(crate) struct Query {
+    /// Words to look for in the captions and the text in the images,
+    /// in the syntax of web search engines: `"quoted phrases"`, `or`,
+    /// and `-excluded` words.
+    pub(crate) text: String,
+    pub(crate) channel: Option<String>,
+    pub(crate) account: Option<String>,
+    pub(crate) since: Option<NaiveDate>,
+    pub(crate) until: Option<NaiveDate>,
+    pub(crate) spoiler: Option<bool>,
+    /// Only memes with this tag, with or without the leading `#`.
+    pub(crate) tag: Option<String>,
+    pub(crate) limit: i64,
+    /// Skip this many matches, for paging through them.
+    pub(crate) offset: i64,
+}
+
+/// Some words of a search, at least one of which must match, or, if
+/// `excluded`, none of which may match.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Clause {
+    alternatives: Vec<String>,
+    excluded: bool,
+}
+
+/// Split `text` into clauses, following `websearch_to_tsquery`:
+/// words and `"quoted phrases"` must all match, unless joined by
+/// `or`, and words or phrases starting with `-` must not match.
+fn parse(text: &str) -> Vec<Clause> {
+    let mut clauses: Vec<Clause> = Vec::new();
+    let mut alternative = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        if char.is_whitespace() {
+            continue;
+        }
+
+        let excluded = char == '-' && chars.peek().is_some_and(|next| !next.is_whitespace());
+        let first = if excluded { chars.next() } else { Some(char) };
+        let mut term = String::new();
+        let quoted = first == Some('"');
+        if quoted {
+            while let Some(char) = chars.next_if(|char| *char != '"') {
+                term.push(char);
+            }
+            chars.next();
+        } else {
+            term.extend(first);
+            while let Some(char) = chars.next_if(|char| !char.is_whitespace()) {
+                term.push(char);
+            }
+        }
+
+        let term = term.trim().to_string();
+        if term.is_empty() {
+            continue;
+        }
+        if !quoted && !excluded && term.eq_ignore_ascii_case("or") {
+            alternative = clauses.last().is_some_and(|clause| !clause.excluded);
+            continue;
+        }
+
+        match clauses.last_mut() {
+            Some(clause) if alternative && !excluded => clause.alternatives.push(term),
+            _ => clauses.push(Clause {
+                alternatives: vec![term],
+                excluded,
+            }),
+        }
+        alternative = false;
+    }
+
+    clauses
+}
+
+/// Escape the wildcards of `LIKE` in `text`, using `\`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Find the memes matching `query`, best matches first.
+///
+/// Besides the caption, the text recognised in the images of a meme
+/// is searched as well. PostgreSQL searches the German and English
+/// full-text index of both, so that inflected forms match as well.
+/// The `search` columns only exist there, which is why they are not
+/// part of the schema. Other databases fall back to matching each
+/// word or phrase as a substring, newest memes first.
+pub(crate) fn find(db: &mut AnyConnection, query: &Query) -> Result<Vec<Meme>> {
+    use db::schema::{images, meme_tags, memes, tags};
+    use diesel::{
+        dsl::{exists, not},
+        prelude::*,
+    };
+
+    type Condition = Box<dyn BoxableExpression<memes::table, MultiBackend, SqlType = Bool>>;
+
+    let mut memes = memes::table
+        .select(Meme::as_select())
+        .into_boxed::<MultiBackend>();
+
+    if let Some(channel) = &query.channel {
+        memes = memes.filter(memes::channel.eq(channel));
+    }
+    if let Some(account) = &query.account {
+        memes = memes.filter(memes::account.eq(account));
+    }
+    if let Some(since) = query.since {
+        memes = memes.filter(memes::timestamp.ge(since.and_time(Default::default())));
+    }
+    if let Some(until) = query
+        .until
+        .and_then(|until| until.checked_add_days(Days::new(1)))
+    {
+        memes = memes.filter(memes::timestamp.lt(until.and_time(Default::default())));
+    }
+    if let Some(spoiler) = query.spoiler {
+        memes = memes.filter(memes::spoiler.eq(spoiler));
+    }
+    if let Some(tag) = &query.tag {
+        memes = memes.filter(exists(
+            meme_tags::table
+                .inner_join(tags::table)
+                .filter(meme_tags::meme_id.eq(memes::id))
+                .filter(tags::name.eq(tag.trim_start_matches('#').to_lowercase())),
+        ));
+    }
+
+    let text = query.text.trim();
+    if text.is_empty() {
+        memes = memes.order(memes::timestamp.desc());
+    } else if let AnyConnection::Postgresql(_) = db {
+        // match either language, so that mixed captions are found
+        macro_rules! tsquery {
+            ($sql:expr) => {
+                $sql.sql("(websearch_to_tsquery('german', ")
+                    .bind::<Text, _>(text.to_string())
+                    .sql(") || websearch_to_tsquery('english', ")
+                    .bind::<Text, _>(text.to_string())
+                    .sql("))")
+            };
+        }
+
+        let matches = sql::<Bool>(r#"("memes"."search" @@ "#);
+        let matches = tsquery!(matches).sql(
+            r#" OR EXISTS (SELECT FROM "images" WHERE "images"."meme_id" = "memes"."id" AND "images"."search" @@ "#,
+        );
+        let matches = tsquery!(matches).sql("))");
+        let rank = sql::<Float>(r#"ts_rank("memes"."search", "#);
+        let rank = tsquery!(rank).sql(r#") + coalesce((SELECT max(ts_rank("images"."search", "#);
+        let rank =
+            tsquery!(rank).sql(r#")) FROM "images" WHERE "images"."meme_id" = "memes"."id"), 0)"#);
+
+        memes = memes
+            .filter(matches)
+            .order((rank.desc(), memes::timestamp.desc()));
+    } else {
+        let contains = |term: &str| -> Condition {
+            let pattern = format!("%{}%", escape_like(term));
+            Box::new(
+                memes::text.like(pattern.clone()).escape('\\').or(exists(
+                    images::table
+                        .filter(images::meme_id.eq(memes::id))
+                        .filter(images::ocr_text.like(pattern).escape('\\')),
+                )),
+            )
+        };
+
+        for clause in parse(text) {
+            let mut alternatives = clause.alternatives.iter().map(|term| contains(term));
+            let Some(first) = alternatives.next() else {
+                continue;
+            };
+            let matches = alternatives.fold(first, |matches, alternative| -> Condition {
+                Box::new(matches.or(alternative))
+            });
+
+            memes = if clause.excluded {
+                memes.filter(not(matches))
+            } else {
+                memes.filter(matches)
+            };
+        }
+        memes = memes.order(memes::timestamp.desc());
+    }
+
+    Ok(memes.limit(query.limit).offset(query.offset).load(db)?)
+}
+
+/// Print the memes matching `query`, along with their files.
+pub(crate) fn search(database: &DatabaseConfiguration, query: &Query) -> Result<()> {
+    use db::schema::images;
+    use diesel::prelude::*;
+
+    let mut db = db::connect(database.url())?;
+    let found = find(&mut db, query)?;
+
+    for meme in &found {
+        let files = images::table
+            .filter(images::meme_id.eq(meme.id))
+            .order(images::position)
+            .select(images::filename)
+            .load::<String>(&mut db)?;
+        let tags = tags::of_meme(&mut db, meme.id)?;
+
+        println!(
+            "#{} {} in {} by {}{}",
+            meme.id,
+            meme.timestamp.format("%Y-%m-%d %H:%M"),
+            meme.channel,
+            meme.account,
+            if meme.spoiler { " (spoiler)" } else { "" }
+        );
+        for line in meme.text.lines().filter(|line| !line.trim().is_empty()) {
+            println!("    {line}");
+        }
+        if !tags.is_empty() {
+            println!("    tags: {}", tags.join(", "));
+        }
+        println!("    files: {}", files.join(", "));
+    }
+
+    log::info!("found {} memes", found.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::{Clause, escape_like, parse};
+
+    fn clause(alternatives: &[&str], excluded: bool) -> Clause {
+        Clause {
+            alternatives: alternatives.iter().map(|term| term.to_string()).collect(),
+            excluded,
+        }
+    }
+
+    #[test]
+    fn parses_web_search_syntax() {
+        assert_eq!(
+            parse(r#"drucker "brennt schon wieder" -toner katze OR hund"#),
+            vec![
+                clause(&["drucker"], false),
+                clause(&["brennt schon wieder"], false),
+                clause(&["toner"], true),
+                clause(&["katze", "hund"], false),
+            ]
+        );
+        assert_eq!(
+            parse(r#"or - -"nicht das" "offen"#),
+            vec![
+                clause(&["-"], false),
+                clause(&["nicht das"], true),
+                clause(&["offen"], false),
+            ]
+        );
+        assert_eq!(parse("  "), vec![]);
+    }
+
+    #[test]
+    fn escapes_wildcards() {
+        assert_eq!(escape_like(r"100% c:\new_file"), r"100\% c:\\new\_file");
+    }
+}
@@ -0,0 +1,141 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use anyhow::Result;
+use diesel::dsl::{delete, insert_into};
+use itertools::Itertools;
+
+use super::db::{self, AnyConnection};
+
+/// The `#hashtags` in `text`, lowercased and without duplicates. A
+/// hashtag starts after whitespace or an opening bracket or quote,
+/// and consists of letters, digits and underscores, but not of digits
+/// alone.
+pub(super) fn extract(text: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut previous = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, char)) = chars.next() {
+        let at_word_start = previous.is_none_or(|previous: char| {
+            previous.is_whitespace() || "([{\"'„“«".contains(previous)
+        });
+        previous = Some(char);
+
+        if char != '#' || !at_word_start {
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some(&(index, next)) = chars.peek() {
+            if !(next.is_alphanumeric() || next == '_') {
+                break;
+            }
+            end = index + next.len_utf8();
+            previous = Some(next);
+            chars.next();
+        }
+
+        let tag = &text[start + 1..end];
+        if !tag.is_empty() && !tag.chars().all(|char| char.is_ascii_digit()) {
+            tags.push(tag.to_lowercase());
+        }
+    }
+
+    tags.into_iter().unique().collect()
+}
+
+/// Make the tags of meme `meme` match the hashtags in `text`,
+/// creating tags as necessary.
+pub(super) fn sync(db: &mut AnyConnection, meme: i32, text: &str) -> Result<()> {
+    use db::schema::{meme_tags, tags};
+    use diesel::prelude::*;
+
+    let mut ids = Vec::new();
+    for name in extract(text) {
+        let id = match tags::table
+            .filter(tags::name.eq(&name))
+            .select(tags::id)
+            .first::<i32>(db)
+            .optional()?
+        {
+            Some(id) => id,
+            None => insert_into(tags::table)
+                .values(tags::name.eq(&name))
+                .returning(tags::id)
+                .get_result::<i32>(db)?,
+        };
+        ids.push(id);
+    }
+
+    delete(
+        meme_tags::table
+            .filter(meme_tags::meme_id.eq(meme))
+            .filter(meme_tags::tag_id.ne_all(&ids)),
+    )
+    .execute(db)?;
+
+    let existing = meme_tags::table
+        .filter(meme_tags::meme_id.eq(meme))
+        .select(meme_tags::tag_id)
+        .load::<i32>(db)?;
+    for id in ids.into_iter().filter(|id| !existing.contains(id)) {
+        insert_into(meme_tags::table)
+            .values((meme_tags::meme_id.eq(meme), meme_tags::tag_id.eq(id)))
+            .execute(db)?;
+    }
+
+    Ok(())
+}
+
+/// The tags of meme `meme`, sorted by name.
+pub(crate) fn of_meme(db: &mut AnyConnection, meme: i32) -> Result<Vec<String>> {
+    use db::schema::{meme_tags, tags};
+    use diesel::prelude::*;
+
+    Ok(meme_tags::table
+        .inner_join(tags::table)
+        .filter(meme_tags::meme_id.eq(meme))
+        .select(tags::name)
+        .order(tags::name)
+        .load(db)?)
+}
+
+/// All tags that are in use, along with the number of memes tagged
+/// with them, most used first.
+pub(crate) fn counts(db: &mut AnyConnection) -> Result<Vec<(String, i64)>> {
+    use db::schema::{meme_tags, tags};
+    use diesel::{dsl::count_star, prelude::*};
+
+    Ok(tags::table
+        .inner_join(meme_tags::table)
+        .group_by(tags::name)
+        .select((tags::name, count_star()))
+        .order((count_star().desc(), tags::name))
+        .load(db)?)
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::extract;
+
+    #[test]
+    fn extracts_hashtags() {
+        assert_eq!(
+            extract("#Katzen are the best #cats, #katzen! #2025 #über_alles"),
+            vec!["katzen", "cats", "über_alles"]
+        );
+    }
+
+    #[test]
+    fn ignores_non_hashtags() {
+        assert_eq!(
+            extract("issue#12 https://example.com/#anchor ## # (#_ok)"),
+            vec!["_ok"]
+        );
+    }
+}
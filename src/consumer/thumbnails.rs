@@ -0,0 +1,327 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::io::Cursor;
+
+use anyhow::{Result, bail};
+use image::{
+    DynamicImage,
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+};
+use tokio::task::spawn_blocking;
+
+use super::{connection, db, with_db};
+use crate::{
+    config::{
+        DatabaseConfiguration, ProcessingConfiguration, StorageConfiguration,
+        ThumbnailConfiguration, ThumbnailFormat,
+    },
+    storage::{Backend, Storage},
+};
+
+/// Where thumbnails live, next to the originals.
+const PREFIX: &str = "thumbnails/";
+const FORMATS: [ThumbnailFormat; 2] = [ThumbnailFormat::Webp, ThumbnailFormat::Jpeg];
+const JPEG_QUALITY: u8 = 85;
+
+fn extension(format: ThumbnailFormat) -> &'static str {
+    match format {
+        ThumbnailFormat::Webp => "webp",
+        ThumbnailFormat::Jpeg => "jpg",
+    }
+}
+
+/// The thumbnail of `file` of size `size`, e.g.,
+/// `thumbnails/320/ab/cd/abcd….jpg.webp`.
+fn name(file: &str, size: u32, format: ThumbnailFormat) -> String {
+    format!("{PREFIX}{size}/{file}.{}", extension(format))
+}
+
+/// The file that the thumbnail `name` was generated from, if `name`
+/// is a thumbnail at all.
+pub(super) fn original(name: &str) -> Option<&str> {
+    let (size, file) = name.strip_prefix(PREFIX)?.split_once('/')?;
+    size.parse::<u32>().ok()?;
+    let (file, format) = file.rsplit_once('.')?;
+
+    FORMATS
+        .iter()
+        .any(|candidate| extension(*candidate) == format)
+        .then_some(file)
+}
+
+/// Every name a thumbnail of `file` might have in `sizes`.
+fn all_names(file: &str, sizes: &[u32]) -> Vec<String> {
+    sizes
+        .iter()
+        .flat_map(|size| FORMATS.map(|format| name(file, *size, format)))
+        .collect()
+}
+
+/// The sizes that `storage` holds thumbnails in, including those that
+/// are no longer configured.
+async fn stored_sizes(storage: &impl Storage) -> Result<Vec<u32>> {
+    Ok(storage
+        .directories(PREFIX)
+        .await?
+        .iter()
+        .filter_map(|size| size.parse().ok())
+        .collect())
+}
+
+/// Remove all thumbnails of each of `files`, whatever their size and
+/// format.
+pub(super) async fn delete_all(storage: &impl Storage, files: &[String]) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let sizes = stored_sizes(storage).await?;
+    for file in files {
+        for thumbnail in all_names(file, &sizes) {
+            storage.delete(&thumbnail).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates scaled-down copies of images. Without any sizes, there
+/// are no thumbnails at all.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Thumbnails {
+    sizes: Vec<u32>,
+    format: ThumbnailFormat,
+}
+
+impl Thumbnails {
+    pub(super) fn new(config: Option<&ThumbnailConfiguration>) -> Self {
+        config
+            .map(|config| Self {
+                sizes: config.sizes().to_vec(),
+                format: config.format(),
+            })
+            .unwrap_or_default()
+    }
+
+    /// The thumbnails of `file`, largest first.
+    pub(super) fn names(&self, file: &str) -> Vec<String> {
+        let mut sizes = self.sizes.clone();
+        sizes.sort_unstable_by(|left, right| right.cmp(left));
+
+        sizes
+            .into_iter()
+            .map(|size| name(file, size, self.format))
+            .collect()
+    }
+
+    fn encode(&self, image: &DynamicImage) -> Result<Vec<u8>> {
+        let mut data = Cursor::new(Vec::new());
+        match self.format {
+            ThumbnailFormat::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut data))?,
+            ThumbnailFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY))?,
+        }
+
+        Ok(data.into_inner())
+    }
+
+    /// The thumbnails of `file`, holding `image`, along with their
+    /// contents. Images are never scaled up. This is CPU-bound, so
+    /// call it on a blocking thread.
+    pub(super) fn generate(&self, file: &str, image: &DynamicImage) -> Vec<(String, Vec<u8>)> {
+        let mut thumbnails = Vec::new();
+        for size in &self.sizes {
+            let thumbnail = if image.width() <= *size && image.height() <= *size {
+                image.clone()
+            } else {
+                image.thumbnail(*size, *size)
+            };
+
+            match self.encode(&thumbnail) {
+                Ok(thumbnail) => thumbnails.push((name(file, *size, self.format), thumbnail)),
+                Err(err) => log::warn!("failed to generate thumbnail of {file}: {err}"),
+            }
+        }
+
+        thumbnails
+    }
+}
+
+/// Generate the thumbnails of all stored images that are missing
+/// some, or, with `all`, of every image.
+pub(crate) async fn regen_thumbnails(
+    storage: &StorageConfiguration,
+    processing: &ProcessingConfiguration,
+    database: &DatabaseConfiguration,
+    all: bool,
+) -> Result<()> {
+    use db::schema::images;
+    use diesel::prelude::*;
+
+    let Some(config) = processing.thumbnails() else {
+        bail!(
+            "thumbnails are not enabled, add a [processing.thumbnails] section to the configuration"
+        );
+    };
+    let thumbnails = Thumbnails::new(Some(config));
+    let storage = Backend::open(storage)?;
+    let pool = db::pool(database.url());
+    db::migrate(&mut *connection(&pool).await)?;
+
+    let files = with_db(&pool, |db| {
+        Ok(images::table
+            .select(images::filename)
+            .distinct()
+            .order(images::filename)
+            .load::<String>(db)?)
+    })
+    .await?;
+
+    let mut generated = 0;
+    for file in &files {
+        if !all {
+            let mut complete = true;
+            for name in thumbnails.names(file) {
+                complete &= storage.exists(&name).await?;
+            }
+            if complete {
+                continue;
+            }
+        }
+
+        let data = match storage.get(file).await {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn!("skipping thumbnails of {file}: {err}");
+                continue;
+            }
+        };
+        // files that aren't images, e.g., videos, have no thumbnails
+        let rendered = spawn_blocking({
+            let (thumbnails, file) = (thumbnails.clone(), file.clone());
+            move || match image::load_from_memory(&data) {
+                Ok(image) => thumbnails.generate(&file, &image),
+                Err(_) => Vec::new(),
+            }
+        })
+        .await?;
+
+        for (name, data) in &rendered {
+            storage.put(name, data).await?;
+        }
+        if !rendered.is_empty() {
+            generated += 1;
+        }
+    }
+    log::info!(
+        "generated thumbnails for {generated} of {} files",
+        files.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use image::{DynamicImage, RgbImage};
+    use test_log::test;
+
+    use super::{Thumbnails, all_names, delete_all, original};
+    use crate::{
+        config::ThumbnailFormat,
+        storage::{LocalStorage, Storage},
+    };
+
+    fn black(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+    }
+
+    #[test]
+    fn names_lead_back_to_originals() {
+        let thumbnails = Thumbnails {
+            sizes: vec![320, 960],
+            format: ThumbnailFormat::Webp,
+        };
+
+        assert_eq!(
+            thumbnails.names("ab/cd/abcd.jpg"),
+            vec![
+                "thumbnails/960/ab/cd/abcd.jpg.webp",
+                "thumbnails/320/ab/cd/abcd.jpg.webp"
+            ]
+        );
+        assert_eq!(all_names("ab/cd/abcd.jpg", &[320, 960]).len(), 4);
+        for name in all_names("ab/cd/abcd.jpg", &[320, 960]) {
+            assert_eq!(original(&name), Some("ab/cd/abcd.jpg"));
+        }
+        assert_eq!(original("ab/cd/abcd.jpg"), None);
+        assert_eq!(original("thumbnails/big/ab/cd/abcd.jpg.webp"), None);
+        assert_eq!(original("thumbnails/320/ab/cd/abcd.jpg.bin"), None);
+    }
+
+    #[test(tokio::test)]
+    async fn deletes_thumbnails_of_any_size() {
+        let root = tempfile::tempdir().expect("can create directory");
+        let storage = LocalStorage::new(root.path());
+        for name in [
+            "ab/cd/abcd.jpg",
+            "thumbnails/320/ab/cd/abcd.jpg.webp",
+            "thumbnails/640/ab/cd/abcd.jpg.jpg",
+            "thumbnails/640/ab/cd/abce.jpg.jpg",
+        ] {
+            storage.put(name, b"meme").await.expect("can put");
+        }
+
+        delete_all(&storage, &["ab/cd/abcd.jpg".to_string()])
+            .await
+            .expect("can delete");
+        assert_eq!(
+            storage.list().await.expect("can list"),
+            vec!["ab/cd/abcd.jpg", "thumbnails/640/ab/cd/abce.jpg.jpg"]
+        );
+    }
+
+    #[test]
+    fn scales_down() {
+        let thumbnails = Thumbnails {
+            sizes: vec![32, 1000],
+            format: ThumbnailFormat::Jpeg,
+        };
+
+        let generated = thumbnails.generate("ab/cd/abcd.png", &black(400, 200));
+        let dimensions = generated
+            .iter()
+            .map(|(name, data)| {
+                let image = image::load_from_memory(data).expect("can decode");
+                (name.as_str(), image.width(), image.height())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            dimensions,
+            vec![
+                ("thumbnails/32/ab/cd/abcd.png.jpg", 32, 16),
+                ("thumbnails/1000/ab/cd/abcd.png.jpg", 400, 200)
+            ]
+        );
+    }
+
+    #[test]
+    fn generates_configured_sizes() {
+        let thumbnails = Thumbnails {
+            sizes: vec![320],
+            format: ThumbnailFormat::Webp,
+        };
+
+        assert!(
+            Thumbnails::default()
+                .generate("ab/cd/abcd.png", &black(4, 4))
+                .is_empty()
+        );
+        assert_eq!(thumbnails.generate("ab/cd/abcd.png", &black(4, 4)).len(), 1);
+    }
+}
@@ -8,31 +8,77 @@ mod config;
 mod consumer;
 mod matrix;
 mod service;
+mod storage;
 mod telegram;
+mod web;
 
-use anyhow::Result;
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use chrono::NaiveDate;
 use clap::Parser;
-use cli::Cli;
+use cli::{Cli, Command};
 use config::Configuration;
-use consumer::Consumer;
+use consumer::{BackfillProgress, Consumer};
 use env_logger::Env;
-#[allow(unused)]
 use matrix::Matrix;
 use service::{Notifications, ReloadSignals, ShutdownSignals};
 use telegram::Telegram;
+use web::Web;
 
 async fn process() -> Result<()> {
-    Notifications::starting()?;
     let args = Cli::parse();
-    let mut configuration = Configuration::load(args.config.clone())?;
+
+    match args.command {
+        None => serve(args.config).await,
+        Some(Command::Backfill {
+            telegram_group,
+            since,
+            until,
+        }) => backfill(args.config, telegram_group, since, until).await,
+        Some(Command::Fsck { repair }) => fsck(args.config, repair).await,
+        Some(Command::Replay) => replay(args.config).await,
+        Some(Command::ReindexOcr { all }) => reindex_ocr(args.config, all).await,
+        Some(Command::RegenThumbnails { all }) => regen_thumbnails(args.config, all).await,
+        Some(Command::Search {
+            query,
+            channel,
+            account,
+            since,
+            until,
+            spoiler,
+            no_spoiler,
+            limit,
+        }) => {
+            let query = consumer::Query {
+                text: query.join(" "),
+                channel,
+                account,
+                since,
+                until,
+                spoiler: (spoiler || no_spoiler).then_some(spoiler),
+                limit,
+                ..Default::default()
+            };
+            search(args.config, query)
+        }
+    }
+}
+
+async fn serve(config: PathBuf) -> Result<()> {
+    Notifications::starting()?;
+    let mut configuration = Configuration::load(config.clone())?;
     let mut reload_signals = ReloadSignals::new()?;
     let mut shutdown_signals = ShutdownSignals::new()?;
     let (mut consumer, meme_consumer) = Consumer::new(
         configuration.storage().clone(),
+        configuration.processing().clone(),
         configuration.database().clone(),
-    )?;
+    )
+    .await?;
     let mut telegram = Telegram::new(configuration.telegram()?, meme_consumer.clone())?;
-    //let mut matrix = Matrix::new(configuration.matrix()?, meme_consumer)?;
+    let mut matrix = Matrix::new(configuration.matrix()?, meme_consumer)?;
+    let mut web = web(&configuration)?;
     log::info!("running");
     Notifications::ready()?;
 
@@ -41,16 +87,34 @@ async fn process() -> Result<()> {
             _ = reload_signals.reload() => {
                 Notifications::reloading()?;
                 log::info!("reloading");
-                configuration = Configuration::load(args.config.clone())?;
-                consumer = consumer.reload(configuration.storage().clone(), configuration.database().clone()).await?;
+                configuration = Configuration::load(config.clone())?;
+                consumer = consumer.reload(configuration.storage().clone(), configuration.processing().clone(), configuration.database().clone()).await?;
                 telegram = telegram.reload(configuration.telegram()?).await?;
-                //matrix = matrix.reload(configuration.matrix()?).await?;
+                matrix = matrix.reload(configuration.matrix()?).await?;
+                // a failed web server is simply started afresh
+                if let Some(web) = web.take()
+                    && let Err(err) = web.shutdown().await
+                {
+                    log::error!("failed to shut down web server: {err}");
+                }
+                web = self::web(&configuration)?;
                 Notifications::ready()?;
             }
             _ = shutdown_signals.shutdown() => {
                 Notifications::stopping()?;
                 log::info!("shutting down");
-                //matrix.shutdown().await?;
+                // the archive is only a view, so it shouldn't keep the
+                // rest from stopping
+                if let Some(web) = web.take()
+                    && let Err(err) = web.shutdown().await
+                {
+                    log::error!("failed to shut down web server: {err}");
+                }
+                // neither should a failed matrix bot keep the memes
+                // already queued from being handled
+                if let Err(err) = matrix.shutdown().await {
+                    log::error!("failed to shut down matrix bot: {err}");
+                }
                 telegram.shutdown().await?;
                 consumer.shutdown().await?;
                 break;
@@ -61,6 +125,104 @@ async fn process() -> Result<()> {
     Ok(())
 }
 
+/// Serve the archive over HTTP, if configured.
+fn web(configuration: &Configuration) -> Result<Option<Web>> {
+    configuration
+        .http()
+        .map(|http| {
+            Web::new(
+                http.clone(),
+                configuration.storage().clone(),
+                configuration.processing().clone(),
+                configuration.database().clone(),
+            )
+        })
+        .transpose()
+}
+
+async fn backfill(
+    config: PathBuf,
+    group: i64,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+    let mut shutdown_signals = ShutdownSignals::new()?;
+    let telegram = configuration.telegram()?;
+    let Some(channel) = telegram
+        .groups()
+        .find(|known| known.id == group)
+        .map(|known| known.name.clone())
+    else {
+        bail!("group {group} is not configured");
+    };
+    let progress = BackfillProgress::load(configuration.database(), group, channel).await?;
+    let (consumer, meme_consumer) = Consumer::new(
+        configuration.storage().without_spool(),
+        configuration.processing().clone(),
+        configuration.database().clone(),
+    )
+    .await?;
+
+    let result = tokio::select! {
+        result = telegram::backfill(telegram, meme_consumer, progress, group, since, until) => result,
+        _ = shutdown_signals.shutdown() => {
+            log::info!("interrupted, progress has been saved");
+            Ok(())
+        }
+    };
+
+    consumer.shutdown().await?;
+    result
+}
+
+async fn fsck(config: PathBuf, repair: bool) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+
+    consumer::fsck(configuration.storage(), configuration.database(), repair).await
+}
+
+async fn replay(config: PathBuf) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+
+    consumer::replay(
+        configuration.storage(),
+        configuration.processing(),
+        configuration.database(),
+    )
+    .await
+}
+
+async fn reindex_ocr(config: PathBuf, all: bool) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+
+    consumer::reindex_ocr(
+        configuration.storage(),
+        configuration.processing(),
+        configuration.database(),
+        all,
+    )
+    .await
+}
+
+async fn regen_thumbnails(config: PathBuf, all: bool) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+
+    consumer::regen_thumbnails(
+        configuration.storage(),
+        configuration.processing(),
+        configuration.database(),
+        all,
+    )
+    .await
+}
+
+fn search(config: PathBuf, query: consumer::Query) -> Result<()> {
+    let configuration = Configuration::load(config)?;
+
+    consumer::search(configuration.database(), &query)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     eprintln!("initialising logging");
@@ -3,50 +3,91 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path, time::Duration};
 
-use anyhow::{Context, Error, Result};
+use anyhow::{Context, Result};
 
-use matrix_sdk::{Client, LoopCtrl, config::SyncSettings, reqwest::Url};
+use chrono::{DateTime, Utc};
+use matrix_sdk::{
+    Client,
+    authentication::matrix::MatrixSession,
+    config::SyncSettings,
+    media::{MediaFormat, MediaRequestParameters},
+    reqwest::Url,
+    room::Room,
+    ruma::{
+        OwnedRoomOrAliasId,
+        api::client::error::ErrorKind,
+        events::room::{
+            message::{MessageType, OriginalSyncRoomMessageEvent, Relation},
+            redaction::OriginalSyncRoomRedactionEvent,
+        },
+    },
+};
 use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
     select,
-    sync::{
-        mpsc::{self, Receiver, Sender},
-        oneshot,
-    },
+    sync::mpsc::{self, Receiver, Sender},
     task::JoinHandle,
+    time::sleep,
 };
 
-use crate::{config, consumer::MemeEvent};
+use crate::{
+    config,
+    consumer::{MediaKind, MemeEvent, MemeImage, MemeSender, Source},
+};
 
-#[allow(unused)]
 #[derive(Debug)]
 pub struct Matrix {
-    task: JoinHandle<Result<Sender<MemeEvent>, Error>>,
+    task: JoinHandle<Result<()>>,
     control: Sender<Command>,
+    /// kept here, so that a restart works even if the bot failed
+    consumer: MemeSender,
 }
 
-#[allow(unused)]
+/// How long to wait before reconnecting after a failure, doubling for
+/// each further one.
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
 impl Matrix {
-    pub(crate) fn new(config: config::Matrix, consumer: Sender<MemeEvent>) -> Result<Self> {
-        let (tx, rx) = mpsc::channel(8);
-        let task = tokio::spawn(async move {
-            let result = process(config, rx, consumer).await;
-            if let Err(ref err) = result {
-                log::error!("{err}");
+    pub(crate) fn new(config: config::Matrix, consumer: MemeSender) -> Result<Self> {
+        let (tx, mut rx) = mpsc::channel(8);
+        let task = tokio::spawn({
+            let consumer = consumer.clone();
+            async move {
+                let mut delay = BACKOFF;
+                loop {
+                    match process(&config, &mut rx, consumer.clone()).await {
+                        Ok(()) => return Ok(()),
+                        Err(err) => log::error!("{err}, reconnecting in {delay:?}"),
+                    }
+
+                    select! {
+                        _ = sleep(delay) => {}
+                        _ = rx.recv() => return Ok(()),
+                    }
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
             }
-            result
         });
 
-        Ok(Self { task, control: tx })
+        Ok(Self {
+            task,
+            control: tx,
+            consumer,
+        })
     }
 
+    /// Restart with `config`. A failure to stop the old bot is only
+    /// logged, the new one starts regardless.
     pub(crate) async fn reload(self, config: config::Matrix) -> Result<Self> {
         log::info!("restarting matrix bot");
-        if !self.control.is_closed() {
-            self.control.send(Command::Shutdown).await?;
+        let consumer = self.consumer.clone();
+        if let Err(err) = self.shutdown().await {
+            log::error!("failed to stop matrix bot: {err}");
         }
-        let consumer = self.task.await??;
         Self::new(config, consumer)
     }
 
@@ -60,26 +101,56 @@ impl Matrix {
     }
 }
 
-#[allow(unused)]
 #[derive(Debug)]
 enum Command {
     Shutdown,
 }
 
-#[allow(unused)]
 type RoomMap = HashMap<String, String>;
 
-#[allow(unused)]
-async fn process(
-    config: config::Matrix,
-    mut control: Receiver<Command>,
-    consumer: Sender<MemeEvent>,
-) -> Result<Sender<MemeEvent>> {
-    log::info!("starting matrix bot");
+/// Write the session to `path`, readable only by us, since it holds
+/// the access token. Renaming is atomic, so a crash never leaves a
+/// truncated session behind.
+async fn save_session(session: &MatrixSession, path: &Path) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)
+        .await?;
+    file.write_all(&serde_json::to_vec(session)?).await?;
+    file.sync_all().await?;
+    fs::rename(&temporary, path).await?;
 
+    Ok(())
+}
+
+/// Connect to the homeserver and log in. With a session file, the
+/// session and the client state are kept across restarts, so that we
+/// don't register a new device every time.
+async fn connect(config: &config::Matrix) -> Result<Client> {
     let url = Url::parse(config.homeserver()).context("failed to parse homeserver URL")?;
-    let client = Client::new(url).await?;
+    let builder = Client::builder().homeserver_url(url);
+    let client = match config.session_file() {
+        Some(path) => builder.sqlite_store(path.with_extension("store"), None),
+        None => builder,
+    }
+    .build()
+    .await?;
     log::debug!("connected to homeserver");
+
+    if let Some(path) = config.session_file()
+        && fs::try_exists(path).await?
+    {
+        let session = serde_json::from_slice::<MatrixSession>(&fs::read(path).await?)
+            .with_context(|| format!("invalid session in {path:?}"))?;
+        client.restore_session(session).await?;
+        log::debug!("resuming saved session");
+        return Ok(client);
+    }
+
     let result = client
         .matrix_auth()
         .login_username(config.username(), config.password())
@@ -87,6 +158,25 @@ async fn process(
         .await?;
     log::debug!("{result:#?}");
 
+    if let Some(path) = config.session_file() {
+        let session = client
+            .matrix_auth()
+            .session()
+            .context("logged in without a session")?;
+        save_session(&session, path).await?;
+    }
+
+    Ok(client)
+}
+
+async fn process(
+    config: &config::Matrix,
+    control: &mut Receiver<Command>,
+    consumer: MemeSender,
+) -> Result<()> {
+    log::info!("starting matrix bot");
+
+    let client = connect(config).await?;
     log::debug!("connected to matrix");
     let rooms: RoomMap = HashMap::from_iter(
         config
@@ -94,115 +184,181 @@ async fn process(
             .map(|room| (room.address.clone(), room.name.clone())),
     );
 
-    fn is_relevant(rooms: &mut RoomMap, room: String) -> bool {
-        rooms.contains_key(&room)
+    for address in rooms.keys() {
+        let room = OwnedRoomOrAliasId::try_from(address.as_str())
+            .with_context(|| format!("invalid room address {address}"))?;
+        client.join_room_by_id_or_alias(&room, &[]).await?;
+        log::debug!("joined room {address}");
+    }
+
+    /// Rooms are configured either by id or by alias, so we need to check both.
+    fn room_name(rooms: &RoomMap, room: &Room) -> Option<String> {
+        rooms
+            .get(room.room_id().as_str())
+            .or_else(|| {
+                room.canonical_alias()
+                    .and_then(|alias| rooms.get(alias.as_str()))
+            })
+            .cloned()
     }
 
     async fn handle_message(
-        client: &Client,
-        groups: &mut RoomMap,
-        consumer: Sender<MemeEvent>,
-        //message: update::Message,
-        is_edit: bool,
+        client: Client,
+        rooms: RoomMap,
+        consumer: MemeSender,
+        room: Room,
+        message: OriginalSyncRoomMessageEvent,
     ) -> Result<()> {
-        // if is_relevant(groups, message.chat()) {
-        //     if let Some(Media::Photo(photo)) = message.media() {
-        //         // don't collect disappearing photos
-        //         if photo.ttl_seconds().is_none() {
-        //             let spoiler = photo.is_spoiler();
-        //             let mut bytes = Vec::new();
-        //             let mut download = client.iter_download(&message.media().expect("is present"));
-
-        //             while let Some(chunk) = download.next().await? {
-        //                 bytes.extend(chunk);
-        //             }
-
-        //             let timestamp = if is_edit {
-        //                 message.edit_date().expect("is edited")
-        //             } else {
-        //                 message.date()
-        //             };
-        //             let image = MemeImage::new(
-        //                 bytes,
-        //                 spoiler,
-        //                 message.text().to_string(),
-        //                 timestamp.naive_utc(),
-        //             );
-        //             let source = Source::telegram(
-        //                 message.sender(),
-        //                 groups
-        //                     .get(&message.chat().id())
-        //                     .map(|group| group.name.as_str()),
-        //                 message.id(),
-        //             );
-
-        //             let event = if is_edit {
-        //                 MemeEvent::edit(image, source)
-        //             } else {
-        //                 MemeEvent::new(image, source)
-        //             };
-
-        //             return Ok(consumer.send(event).await?);
-        //         }
-        //     }
-        // }
+        let Some(channel) = room_name(&rooms, &room) else {
+            log::debug!("irrelevant room {}", room.room_id());
+            return Ok(());
+        };
+
+        let (content, event_id, is_edit) = match message.content.relates_to {
+            Some(Relation::Replacement(replacement)) => {
+                (replacement.new_content.msgtype, replacement.event_id, true)
+            }
+            _ => (message.content.msgtype, message.event_id, false),
+        };
+
+        if let MessageType::Image(image) = content {
+            let bytes = client
+                .media()
+                .get_media_content(
+                    &MediaRequestParameters {
+                        source: image.source.clone(),
+                        format: MediaFormat::File,
+                    },
+                    true,
+                )
+                .await?;
+
+            // the body only holds a caption if a separate file name is given
+            let text = match image.filename {
+                Some(ref filename) if filename != &image.body => image.body.clone(),
+                _ => String::new(),
+            };
+            let timestamp = message
+                .origin_server_ts
+                .to_system_time()
+                .map(DateTime::<Utc>::from)
+                .context("invalid message timestamp")?;
+            let mime_type = image
+                .info
+                .as_ref()
+                .and_then(|info| info.mimetype.clone())
+                .unwrap_or_default();
+            let image = MemeImage::new(
+                bytes,
+                MediaKind::Photo,
+                mime_type,
+                false,
+                text,
+                timestamp.naive_utc(),
+            );
+            let source = Source::matrix(
+                Some(message.sender.as_str()),
+                Some(&channel),
+                event_id.as_str(),
+            );
+
+            let event = if is_edit {
+                MemeEvent::edit(image, source)
+            } else {
+                MemeEvent::new(image, source)
+            };
+
+            return consumer.send(event).await;
+        }
+
         Ok(())
     }
 
     async fn handle_delete(
-        consumer: Sender<MemeEvent>,
-        //        message: update::MessageDeletion,
+        rooms: RoomMap,
+        consumer: MemeSender,
+        room: Room,
+        redaction: OriginalSyncRoomRedactionEvent,
     ) -> Result<()> {
-        // assert!(message.channel_id().is_none());
+        if room_name(&rooms, &room).is_none() {
+            log::debug!("irrelevant room {}", room.room_id());
+            return Ok(());
+        }
 
-        // for &id in message.messages() {
-        //     consumer
-        //         .send(MemeEvent::delete(Source::telegram(None, None, id)))
-        //         .await?;
-        // }
+        // starting with room version 11, `redacts` moved into the content
+        if let Some(event_id) = redaction.content.redacts.or(redaction.redacts) {
+            consumer
+                .send(MemeEvent::delete(Source::matrix(
+                    None,
+                    None,
+                    event_id.as_str(),
+                )))
+                .await?;
+        }
 
         Ok(())
     }
 
-    let (tx, rx) = oneshot::channel::<()>();
+    // skip everything that happened before we got here, we only
+    // want to handle new events.
+    let response = match client.sync_once(SyncSettings::default()).await {
+        Ok(response) => response,
+        Err(err) => {
+            // log in afresh next time if the saved session is gone
+            if let Some(ErrorKind::UnknownToken { .. }) = err.client_api_error_kind()
+                && let Some(path) = config.session_file()
+            {
+                log::warn!("saved session is no longer valid, removing {path:?}");
+                fs::remove_file(path).await?;
+            }
+            return Err(err.into());
+        }
+    };
+    log::debug!("initial sync done");
 
-    loop {
-        select! {
-            update = client.sync_with_callback(SyncSettings::default(), async |_| { if rx.is_empty() { LoopCtrl::Continue } else { LoopCtrl::Break }}) => {
-                log::debug!("update: {update:#?}");
+    client.add_event_handler({
+        let rooms = rooms.clone();
+        let consumer = consumer.clone();
+        move |message: OriginalSyncRoomMessageEvent, room: Room, client: Client| {
+            let rooms = rooms.clone();
+            let consumer = consumer.clone();
+            async move {
+                if let Err(err) = handle_message(client, rooms, consumer, room, message).await {
+                    log::error!("{err}");
+                }
             }
-            // update = client.next_update() => {
-            //     match update {
-            //         Ok(Update::NewMessage(message))  => {
-            //             handle_message(&client, &mut groups, consumer.clone(), message, false).await?
-            //         }
-            //         Ok(Update::MessageEdited(message)) => {
-            //             handle_message(&client, &mut groups, consumer.clone(), message, true).await?
-            //         }
-            //         Ok(Update::MessageDeleted(message)) => {
-            //             handle_delete(consumer.clone(), message).await?
-            //         },
-            //         Err(err) => {
-            //             log::error!("error: {err:?}");
-            //         }
-            //         _ => {
-            //         }
-            //     }
-            // }
-
-            Some(command) = control.recv() => {
-                match command {
-                    Command::Shutdown => {
-                        let _ = tx.send(());
-                        break
-                    },
+        }
+    });
+    client.add_event_handler({
+        let rooms = rooms.clone();
+        let consumer = consumer.clone();
+        move |redaction: OriginalSyncRoomRedactionEvent, room: Room| {
+            let rooms = rooms.clone();
+            let consumer = consumer.clone();
+            async move {
+                if let Err(err) = handle_delete(rooms, consumer, room, redaction).await {
+                    log::error!("{err}");
                 }
             }
         }
+    });
+
+    select! {
+        result = client.sync(SyncSettings::default().token(response.next_batch)) => {
+            result?;
+        }
+
+        Some(command) = control.recv() => {
+            match command {
+                Command::Shutdown => {},
+            }
+        }
     }
 
-    client.logout().await?;
+    if config.session_file().is_none() {
+        client.logout().await?;
+    }
     drop(client);
 
-    Ok(consumer)
+    Ok(())
 }
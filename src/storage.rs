@@ -0,0 +1,149 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+mod local;
+mod s3;
+
+use anyhow::Result;
+
+use crate::config::{StorageBackend, StorageConfiguration};
+pub(crate) use local::LocalStorage;
+pub(crate) use s3::S3Storage;
+
+/// A place to keep meme files. Files are identified by relative,
+/// `/`-separated names such as `ab/cd/abcd….jpg`. Writing a file
+/// either stores it completely or not at all, and deleting a file
+/// that does not exist is not an error.
+pub(crate) trait Storage {
+    async fn put(&self, name: &str, data: &[u8]) -> Result<()>;
+    async fn get(&self, name: &str) -> Result<Vec<u8>>;
+    async fn delete(&self, name: &str) -> Result<()>;
+    async fn exists(&self, name: &str) -> Result<bool>;
+    async fn list(&self) -> Result<Vec<String>>;
+    /// The directories directly below `prefix`, e.g., `cd` for
+    /// `ab/cd/abcd….jpg` and the prefix `ab/`.
+    async fn directories(&self, prefix: &str) -> Result<Vec<String>>;
+}
+
+/// The storage backend selected in the configuration.
+#[derive(Debug)]
+pub(crate) enum Backend {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Backend {
+    pub(crate) fn open(config: &StorageConfiguration) -> Result<Self> {
+        Ok(match config.backend() {
+            StorageBackend::Local => Self::Local(LocalStorage::new(config.path())),
+            StorageBackend::S3 => Self::S3(S3Storage::new(&config.s3()?)?),
+        })
+    }
+}
+
+/// A local directory for files that should no longer be part of the
+/// archive, but that we don't want to delete outright. It is hidden
+/// inside the storage path, so the local backend won't list it.
+#[derive(Debug)]
+pub(crate) struct Quarantine {
+    storage: LocalStorage,
+}
+
+impl Quarantine {
+    pub(crate) fn new(config: &StorageConfiguration) -> Self {
+        Self {
+            storage: LocalStorage::new(&config.path().join(".quarantine")),
+        }
+    }
+
+    /// Keep `data` in the quarantine under `name`.
+    pub(crate) async fn keep(&self, name: &str, data: &[u8]) -> Result<()> {
+        log::warn!("quarantining {name}");
+        self.storage.put(name, data).await
+    }
+
+    /// Move the file `name` from `storage` into the quarantine.
+    pub(crate) async fn take(&self, storage: &impl Storage, name: &str) -> Result<()> {
+        let data = storage.get(name).await?;
+        self.keep(name, &data).await?;
+        storage.delete(name).await
+    }
+}
+
+impl Storage for Backend {
+    async fn put(&self, name: &str, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Local(storage) => storage.put(name, data).await,
+            Self::S3(storage) => storage.put(name, data).await,
+        }
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Local(storage) => storage.get(name).await,
+            Self::S3(storage) => storage.get(name).await,
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        match self {
+            Self::Local(storage) => storage.delete(name).await,
+            Self::S3(storage) => storage.delete(name).await,
+        }
+    }
+
+    async fn exists(&self, name: &str) -> Result<bool> {
+        match self {
+            Self::Local(storage) => storage.exists(name).await,
+            Self::S3(storage) => storage.exists(name).await,
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        match self {
+            Self::Local(storage) => storage.list().await,
+            Self::S3(storage) => storage.list().await,
+        }
+    }
+
+    async fn directories(&self, prefix: &str) -> Result<Vec<String>> {
+        match self {
+            Self::Local(storage) => storage.directories(prefix).await,
+            Self::S3(storage) => storage.directories(prefix).await,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::Storage;
+
+    /// Exercise all operations of a storage backend.
+    pub(crate) async fn round_trip(storage: &impl Storage) {
+        let name = "ab/cd/abcd.jpg";
+        let data = b"not really a meme";
+
+        assert!(!storage.exists(name).await.expect("can check"));
+        storage.put(name, data).await.expect("can put");
+        assert!(storage.exists(name).await.expect("can check"));
+        assert_eq!(storage.get(name).await.expect("can get"), data);
+        assert_eq!(storage.list().await.expect("can list"), vec![name]);
+        assert_eq!(
+            storage.directories("ab/").await.expect("can list"),
+            vec!["cd"]
+        );
+        assert!(
+            storage
+                .directories("ef/")
+                .await
+                .expect("can list")
+                .is_empty()
+        );
+        storage.delete(name).await.expect("can delete");
+        assert!(!storage.exists(name).await.expect("can check"));
+        storage.delete(name).await.expect("can delete again");
+        assert!(storage.list().await.expect("can list").is_empty());
+    }
+}
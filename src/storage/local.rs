@@ -0,0 +1,173 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::Result;
+use tokio::{fs, io::AsyncWriteExt};
+
+use super::Storage;
+
+/// Stores files in a directory on the local file system.
+#[derive(Debug)]
+pub(crate) struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub(crate) fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+/// A unique, hidden name next to `path` to write into before moving
+/// the file into place.
+fn temporary_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(
+        ".{name}.{}.{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+impl Storage for LocalStorage {
+    async fn put(&self, name: &str, data: &[u8]) -> Result<()> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        log::debug!("writing to {path:?}");
+
+        // renaming is atomic, so a crash never leaves a truncated file
+        // under the final name
+        let temporary = temporary_path(&path);
+        let result = async {
+            let mut file = fs::File::create(&temporary).await?;
+            file.write_all(data).await?;
+            file.sync_all().await?;
+            fs::rename(&temporary, &path).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temporary).await;
+        }
+
+        Ok(result?)
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.root.join(name)).await?)
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        let path = self.root.join(name);
+        log::debug!("removing {path:?}");
+
+        match fs::remove_file(&path).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                log::warn!("{path:?} is already gone");
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn exists(&self, name: &str) -> Result<bool> {
+        Ok(fs::try_exists(self.root.join(name)).await?)
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut directories = vec![self.root.clone()];
+
+        while let Some(directory) = directories.pop() {
+            let mut entries = fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    // skip files that are still being written
+                    continue;
+                } else if entry.file_type().await?.is_dir() {
+                    directories.push(path);
+                } else if let Ok(name) = path.strip_prefix(&self.root) {
+                    let name = name
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    names.push(name);
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    async fn directories(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut entries = match fs::read_dir(self.root.join(prefix)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') && entry.file_type().await?.is_dir() {
+                names.push(name);
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::LocalStorage;
+    use crate::storage::{Storage, test::round_trip};
+
+    #[test(tokio::test)]
+    async fn local_round_trip() {
+        let root = tempfile::tempdir().expect("can create directory");
+        round_trip(&LocalStorage::new(root.path())).await;
+    }
+
+    #[test(tokio::test)]
+    async fn no_temporary_files_remain() {
+        let root = tempfile::tempdir().expect("can create directory");
+        let storage = LocalStorage::new(root.path());
+        storage.put("ab/meme.png", b"first").await.expect("can put");
+        storage
+            .put("ab/meme.png", b"second")
+            .await
+            .expect("can put");
+
+        let entries = std::fs::read_dir(root.path().join("ab"))
+            .expect("can read directory")
+            .map(|entry| entry.expect("is readable").file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec!["meme.png"]);
+        assert_eq!(
+            storage.get("ab/meme.png").await.expect("can get"),
+            b"second"
+        );
+    }
+}
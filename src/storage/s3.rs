@@ -0,0 +1,122 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use object_store::{ObjectStore, PutPayload, aws::AmazonS3, aws::AmazonS3Builder, path::Path};
+
+use super::Storage;
+use crate::config;
+
+/// Stores files in a bucket of an S3-compatible object store.
+#[derive(Debug)]
+pub(crate) struct S3Storage {
+    store: AmazonS3,
+}
+
+impl S3Storage {
+    pub(crate) fn new(config: &config::S3) -> Result<Self> {
+        let (access_key_id, secret_access_key) = config.credentials();
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(config.bucket())
+            .with_region(config.region())
+            .with_access_key_id(access_key_id)
+            .with_secret_access_key(secret_access_key)
+            .with_allow_http(config.allow_http());
+
+        if let Some(endpoint) = config.endpoint() {
+            // self-hosted stores such as MinIO usually don't support
+            // virtual-hosted-style requests
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(false);
+        }
+
+        Ok(Self {
+            store: builder.build()?,
+        })
+    }
+}
+
+impl Storage for S3Storage {
+    async fn put(&self, name: &str, data: &[u8]) -> Result<()> {
+        log::debug!("uploading {name}");
+        self.store
+            .put(&Path::from(name), PutPayload::from(data.to_vec()))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let result = self.store.get(&Path::from(name)).await?;
+
+        Ok(result.bytes().await?.to_vec())
+    }
+
+    async fn delete(&self, name: &str) -> Result<()> {
+        log::debug!("removing {name}");
+        self.store.delete(&Path::from(name)).await?;
+
+        Ok(())
+    }
+
+    async fn exists(&self, name: &str) -> Result<bool> {
+        match self.store.head(&Path::from(name)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut names = self
+            .store
+            .list(None)
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        names.sort();
+        Ok(names)
+    }
+
+    async fn directories(&self, prefix: &str) -> Result<Vec<String>> {
+        let listing = self
+            .store
+            .list_with_delimiter(Some(&Path::from(prefix)))
+            .await?;
+        let mut names = listing
+            .common_prefixes
+            .iter()
+            .filter_map(|directory| directory.filename().map(str::to_string))
+            .collect::<Vec<_>>();
+
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use object_store::aws::AmazonS3Builder;
+    use test_log::test;
+
+    use super::S3Storage;
+    use crate::storage::test::round_trip;
+
+    /// Run against an empty bucket, e.g. on a local MinIO, with
+    /// `AWS_ENDPOINT`, `AWS_BUCKET_NAME`, `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_ALLOW_HTTP` set accordingly.
+    #[test(tokio::test)]
+    #[ignore = "requires an S3-compatible object store"]
+    async fn s3_round_trip() {
+        let store = AmazonS3Builder::from_env()
+            .with_virtual_hosted_style_request(false)
+            .build()
+            .expect("object store is configured");
+        round_trip(&S3Storage { store }).await;
+    }
+}
@@ -3,31 +3,40 @@
 //
 // SPDX-License-Identifier: EUPL-1.2
 
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::{Error, Result};
+use anyhow::{Error, Result, bail};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 use grammers_client::{
     Client, Config, FixedReconnect, InvocationError,
-    session::Session,
-    types::{Chat, Media, Update, update},
+    session::{PackedChat, PackedType, Session},
+    types::{Chat, Media, Message, Update, update},
 };
+use grammers_tl_types::{self as tl, enums::DocumentAttribute};
 
 use grammers_mtsender::RpcError;
+use serde::{Deserialize, Serialize};
 use tokio::{
     select,
-    sync::{broadcast, mpsc::Sender},
-    task::JoinHandle,
-    time::sleep,
+    sync::broadcast,
+    task::{JoinHandle, spawn_blocking},
+    time::{Instant, sleep, sleep_until},
 };
 
 use crate::{
     config,
-    consumer::{MemeEvent, MemeImage, Source},
+    consumer::{BackfillProgress, MediaKind, MemeEvent, MemeImage, MemeSender, Reaction, Source},
 };
 
 #[derive(Debug)]
 pub struct Telegram {
-    pub(crate) task: JoinHandle<Result<Sender<MemeEvent>, Error>>,
+    pub(crate) task: JoinHandle<Result<MemeSender, Error>>,
     control: broadcast::Sender<Command>,
 }
 
@@ -35,31 +44,26 @@ const RECONNECT_FOREVER: FixedReconnect = FixedReconnect {
     attempts: usize::MAX,
     delay: Duration::from_secs(1),
 };
+/// How long to wait before starting over after a failure, unless
+/// Telegram tells us how long to wait.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
 
 impl Telegram {
-    pub(crate) fn new(config: config::Telegram, consumer: Sender<MemeEvent>) -> Result<Self> {
-        let (tx, _rx) = broadcast::channel(8);
-        let control = tx.clone();
+    pub(crate) fn new(config: config::Telegram, consumer: MemeSender) -> Result<Self> {
+        let (control, mut rx) = broadcast::channel(8);
         let task = tokio::spawn(async move {
             loop {
-                let result = process(config.clone(), tx.subscribe(), consumer.clone()).await;
-                match result {
-                    Err(ref err) => {
+                match process(config.clone(), &mut rx, consumer.clone()).await {
+                    Ok(result) => return Ok(result),
+                    Err(err) => {
                         log::error!("{err}");
 
-                        if let Some(InvocationError::Rpc(RpcError {
-                            name,
-                            code: 420,
-                            value: Some(seconds),
-                            ..
-                        })) = err.downcast_ref()
-                        {
-                            log::warn!("received flood wait {name}, waiting {seconds} seconds");
-                            let delay = Duration::from_secs(u64::from(*seconds));
-                            sleep(delay).await;
+                        let delay = flood_wait(&err).unwrap_or(RESTART_DELAY);
+                        select! {
+                            _ = sleep(delay) => {}
+                            _ = rx.recv() => return Ok(consumer),
                         }
                     }
-                    Ok(result) => return Ok(result),
                 }
             }
         });
@@ -86,6 +90,21 @@ impl Telegram {
     }
 }
 
+fn flood_wait(err: &Error) -> Option<Duration> {
+    if let Some(InvocationError::Rpc(RpcError {
+        name,
+        code: 420,
+        value: Some(seconds),
+        ..
+    })) = err.downcast_ref()
+    {
+        log::warn!("received flood wait {name}, waiting {seconds} seconds");
+        return Some(Duration::from_secs(u64::from(*seconds)));
+    }
+
+    None
+}
+
 #[derive(Clone, Copy, Debug)]
 enum Command {
     Shutdown,
@@ -94,7 +113,11 @@ enum Command {
 #[derive(Debug)]
 struct Group {
     name: String,
-    chat: Option<Chat>,
+    /// how to address the group, including its access hash, once
+    /// we have seen it
+    chat: Option<PackedChat>,
+    /// the newest message we have seen in the group
+    last_message: Option<i32>,
 }
 
 impl From<config::Group> for Group {
@@ -102,142 +125,521 @@ impl From<config::Group> for Group {
         Self {
             name: value.name,
             chat: None,
+            last_message: None,
         }
     }
 }
 
 type GroupMap = HashMap<i64, Group>;
 
-async fn process(
-    config: config::Telegram,
-    mut control: broadcast::Receiver<Command>,
-    consumer: Sender<MemeEvent>,
-) -> Result<Sender<MemeEvent>> {
-    log::info!("starting telegram bot");
+/// What we learned about a group from its updates, kept next to the
+/// session, so that a backfill can address the group and knows where
+/// its history ends.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+struct KnownGroup {
+    /// the packed chat
+    chat: Option<Vec<u8>>,
+    last_message: Option<i32>,
+}
+
+fn known_groups_file(session_file: &Path) -> PathBuf {
+    session_file.with_extension("groups.json")
+}
 
+async fn connect(config: &config::Telegram) -> Result<Client> {
     let (api_id, api_hash) = config.api_credentials();
+    let session = match config.session_file() {
+        Some(path) => Session::load_file_or_create(path)?,
+        None => Session::new(),
+    };
 
     let client = Client::connect(Config {
-        session: Session::new(),
+        session,
         api_id,
         api_hash: api_hash.to_string(),
         params: grammers_client::InitParams {
             reconnection_policy: &RECONNECT_FOREVER,
+            // replay everything we missed since the saved update state
+            // through the regular update handling below
+            catch_up: config.catch_up(),
             ..Default::default()
         },
     })
     .await?;
 
     log::debug!("connected to telegram");
-    let _bot = client.bot_sign_in(config.bot_token()).await?;
-    let mut groups: GroupMap = HashMap::from_iter(
+    if client.is_authorized().await? {
+        log::debug!("resuming saved session");
+        if config.catch_up() {
+            log::info!("catching up on missed updates");
+        }
+    } else {
+        let _bot = client.bot_sign_in(config.bot_token()).await?;
+    }
+
+    Ok(client)
+}
+
+/// Persist the session (including the update state) so that
+/// neither a reload nor a restart requires signing in again.
+fn save_session(client: &Client, path: Option<&Path>) -> Result<()> {
+    if let Some(path) = path {
+        client.sync_update_state();
+        client.session().save_to_file(path)?;
+    }
+
+    Ok(())
+}
+
+fn load_known_groups(path: &Path) -> Result<HashMap<i64, KnownGroup>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// The configured groups, along with what we already know about them.
+/// What we knew is only a convenience, so if it can't be read, we
+/// start over.
+fn groups(config: &config::Telegram) -> GroupMap {
+    let mut groups = HashMap::from_iter(
         config
             .groups()
             .map(|group| (group.id, Group::from(group.clone()))),
     );
 
-    fn is_relevant(groups: &mut GroupMap, chat: Chat) -> bool {
-        if let Some(group) = groups.get_mut(&chat.id()) {
-            group.chat = Some(chat);
-            return true;
-        } else {
-            log::debug!("irrelevant chat {chat:#?}");
+    let Some(path) = config.session_file().map(known_groups_file) else {
+        return groups;
+    };
+    if !path.exists() {
+        return groups;
+    }
+
+    let known = match load_known_groups(&path) {
+        Ok(known) => known,
+        Err(err) => {
+            log::warn!("ignoring unreadable {path:?}: {err}");
+            return groups;
         }
+    };
+    for (id, known) in known {
+        if let Some(group) = groups.get_mut(&id) {
+            group.chat = known.chat.and_then(|chat| {
+                PackedChat::from_bytes(&chat)
+                    .inspect_err(|_| log::warn!("ignoring invalid chat for group {id} in {path:?}"))
+                    .ok()
+            });
+            group.last_message = known.last_message;
+        }
+    }
 
-        false
-    }
-
-    async fn handle_message(
-        client: &Client,
-        groups: &mut GroupMap,
-        consumer: Sender<MemeEvent>,
-        message: update::Message,
-        is_edit: bool,
-    ) -> Result<()> {
-        if is_relevant(groups, message.chat()) {
-            if let Some(Media::Photo(photo)) = message.media() {
-                // don't collect disappearing photos
-                if photo.ttl_seconds().is_none() {
-                    let spoiler = photo.is_spoiler();
-                    let mut bytes = Vec::new();
-                    let mut download = client.iter_download(&message.media().expect("is present"));
-
-                    while let Some(chunk) = download.next().await? {
-                        bytes.extend(chunk);
-                    }
+    groups
+}
+
+/// What we know about `groups`, in the form we keep it.
+fn known_groups(groups: &GroupMap) -> HashMap<i64, KnownGroup> {
+    groups
+        .iter()
+        .map(|(id, group)| {
+            let known = KnownGroup {
+                chat: group.chat.map(|chat| chat.to_bytes().to_vec()),
+                last_message: group.last_message,
+            };
+            (*id, known)
+        })
+        .collect()
+}
+
+/// Remember what we know about the configured groups, if there is a
+/// session file to keep it next to. Renaming is atomic, so a crash
+/// never leaves a torn file behind.
+async fn save_groups(known: &HashMap<i64, KnownGroup>, session_file: Option<&Path>) -> Result<()> {
+    let Some(path) = session_file.map(known_groups_file) else {
+        return Ok(());
+    };
+    let data = serde_json::to_vec(known)?;
+
+    spawn_blocking(move || -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &path)?;
 
-                    let timestamp = if is_edit {
-                        message.edit_date().expect("is edited")
-                    } else {
-                        message.date()
-                    };
-                    let image = MemeImage::new(
-                        bytes,
-                        spoiler,
-                        message.text().to_string(),
-                        timestamp.naive_utc(),
-                    );
-                    let source = Source::telegram(
-                        message.sender(),
-                        groups
-                            .get(&message.chat().id())
-                            .map(|group| group.name.as_str()),
-                        message.id(),
-                    );
-
-                    let event = if is_edit {
-                        MemeEvent::edit(image, source)
-                    } else {
-                        MemeEvent::new(image, source)
-                    };
-
-                    return Ok(consumer.send(event).await?);
-                }
-            }
-        }
         Ok(())
+    })
+    .await?
+}
+
+fn is_relevant(groups: &mut GroupMap, chat: Chat) -> bool {
+    if let Some(group) = groups.get_mut(&chat.id()) {
+        group.chat = Some(chat.pack());
+        return true;
+    } else {
+        log::debug!("irrelevant chat {chat:#?}");
     }
 
-    async fn handle_delete(
-        consumer: Sender<MemeEvent>,
-        message: update::MessageDeletion,
-    ) -> Result<()> {
-        if let Some(channel) = message.channel_id() {
-            log::info!(
-                "got a deletion with channel id set: {channel}, don't know how to handle this."
-            );
+    false
+}
 
-            return Ok(());
+/// How long to wait for the remaining messages of an album after
+/// the latest one arrived.
+const ALBUM_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Album {
+    images: Vec<(i32, MemeImage, Source)>,
+    deadline: Instant,
+}
+
+/// Albums arrive as separate messages sharing a `grouped_id`, so we
+/// collect them for a short while before passing them on as a
+/// single meme.
+#[derive(Debug, Default)]
+struct Albums {
+    pending: HashMap<i64, Album>,
+}
+
+impl Albums {
+    fn add(&mut self, grouped_id: i64, id: i32, image: MemeImage, source: Source) {
+        let deadline = Instant::now() + ALBUM_DELAY;
+        let album = self.pending.entry(grouped_id).or_insert_with(|| Album {
+            images: Vec::new(),
+            deadline,
+        });
+
+        // large albums may take a while to arrive completely
+        album.deadline = deadline;
+        album.images.push((id, image, source));
+    }
+
+    fn first_message(&self) -> Option<i32> {
+        self.pending
+            .values()
+            .flat_map(|album| album.images.iter().map(|(id, _, _)| *id))
+            .min()
+    }
+
+    /// Resolves once the first pending album is complete.
+    async fn expired(&self) {
+        match self.pending.values().map(|album| album.deadline).min() {
+            Some(deadline) => sleep_until(deadline).await,
+            None => std::future::pending().await,
         }
+    }
 
-        for &id in message.messages() {
-            consumer
-                .send(MemeEvent::delete(Source::telegram(None, None, id)))
-                .await?;
+    async fn send_expired(&mut self, consumer: &MemeSender) -> Result<()> {
+        let now = Instant::now();
+        let expired = self
+            .pending
+            .extract_if(|_, album| album.deadline <= now)
+            .collect::<Vec<_>>();
+
+        for (_, album) in expired {
+            Self::send(album, consumer).await?;
         }
 
         Ok(())
     }
 
+    async fn send_all(&mut self, consumer: &MemeSender) -> Result<()> {
+        for (_, album) in self.pending.drain() {
+            Self::send(album, consumer).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn send(mut album: Album, consumer: &MemeSender) -> Result<()> {
+        album.images.sort_by_key(|(id, _, _)| *id);
+        let images = album
+            .images
+            .into_iter()
+            .map(|(_, image, source)| (image, source))
+            .collect();
+
+        consumer.send(MemeEvent::album(images)).await
+    }
+}
+
+/// Determine the kind, MIME type and spoiler flag of some media,
+/// skipping disappearing media and documents that aren't images.
+fn classify(media: &Media) -> Option<(MediaKind, String, bool)> {
+    match media {
+        Media::Photo(photo) if photo.ttl_seconds().is_none() => Some((
+            MediaKind::Photo,
+            "image/jpeg".to_string(),
+            photo.is_spoiler(),
+        )),
+        Media::Sticker(sticker) => Some((
+            MediaKind::Sticker,
+            sticker.document.mime_type().unwrap_or_default().to_string(),
+            sticker.document.raw.spoiler,
+        )),
+        Media::Document(document) if document.raw.ttl_seconds.is_none() => {
+            let mime_type = document.mime_type().unwrap_or_default();
+            let attributes = match &document.raw.document {
+                Some(tl::enums::Document::Document(document)) => document.attributes.as_slice(),
+                _ => &[],
+            };
+
+            let kind = if attributes
+                .iter()
+                .any(|attribute| matches!(attribute, DocumentAttribute::Animated))
+            {
+                MediaKind::Animation
+            } else if attributes
+                .iter()
+                .any(|attribute| matches!(attribute, DocumentAttribute::Video(_)))
+            {
+                MediaKind::Video
+            } else if mime_type.starts_with("image/") {
+                MediaKind::Document
+            } else {
+                return None;
+            };
+
+            Some((kind, mime_type.to_string(), document.raw.spoiler))
+        }
+        _ => None,
+    }
+}
+
+async fn handle_message(
+    client: &Client,
+    config: &config::Telegram,
+    groups: &mut GroupMap,
+    albums: &mut Albums,
+    consumer: MemeSender,
+    message: Message,
+    is_edit: bool,
+) -> Result<()> {
+    if is_relevant(groups, message.chat()) {
+        if let Some(media) = message.media() {
+            if let Some((kind, mime_type, spoiler)) = classify(&media) {
+                if !config.collects(kind) {
+                    log::debug!("not collecting {kind:?} in message {}", message.id());
+                    return Ok(());
+                }
+
+                let mut bytes = Vec::new();
+                let mut download = client.iter_download(&media);
+
+                while let Some(chunk) = download.next().await? {
+                    bytes.extend(chunk);
+                }
+
+                let timestamp = if is_edit {
+                    message.edit_date().expect("is edited")
+                } else {
+                    message.date()
+                };
+                let image = MemeImage::new(
+                    bytes,
+                    kind,
+                    mime_type,
+                    spoiler,
+                    message.text().to_string(),
+                    timestamp.naive_utc(),
+                );
+                let chat = message.chat().id();
+                if let Some(group) = groups.get_mut(&chat) {
+                    group.last_message = group.last_message.max(Some(message.id()));
+                }
+                let source = Source::telegram(
+                    message.sender(),
+                    groups.get(&chat).map(|group| group.name.as_str()),
+                    Some(chat),
+                    !matches!(
+                        message.chat().pack().ty,
+                        PackedType::User | PackedType::Bot | PackedType::Chat
+                    ),
+                    message.id(),
+                );
+
+                if is_edit {
+                    return consumer.send(MemeEvent::edit(image, source)).await;
+                }
+
+                match message.grouped_id() {
+                    Some(grouped_id) => albums.add(grouped_id, message.id(), image, source),
+                    None => return consumer.send(MemeEvent::new(image, source)).await,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_delete(
+    groups: &GroupMap,
+    consumer: MemeSender,
+    message: update::MessageDeletion,
+) -> Result<()> {
+    // message ids are only unique within a channel, so restrict
+    // the deletion to the group it happened in
+    let chat = message.channel_id();
+    if let Some(channel) = chat {
+        if !groups.contains_key(&channel) {
+            log::debug!("irrelevant deletion in channel {channel}");
+            return Ok(());
+        }
+    }
+
+    for &id in message.messages() {
+        consumer
+            .send(MemeEvent::delete(Source::telegram(
+                None,
+                None,
+                chat,
+                chat.is_some(),
+                id,
+            )))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// The emoji of a reaction. Custom emoji are identified by their
+/// document id, paid reactions are ignored.
+fn emoji(reaction: tl::enums::Reaction) -> Option<String> {
+    match reaction {
+        tl::enums::Reaction::Emoji(emoji) => Some(emoji.emoticon),
+        tl::enums::Reaction::CustomEmoji(emoji) => Some(format!("custom:{}", emoji.document_id)),
+        _ => None,
+    }
+}
+
+/// The reactions to a message, by emoji.
+fn reactions(results: Vec<tl::enums::ReactionCount>) -> Vec<Reaction> {
+    results
+        .into_iter()
+        .filter_map(|result| {
+            let tl::enums::ReactionCount::Count(result) = result;
+            Some(Reaction::new(emoji(result.reaction)?, result.count))
+        })
+        .collect()
+}
+
+/// How the reactions to a message change, by emoji, when someone
+/// replaces their reactions `old` with `new`.
+fn reaction_changes(old: Vec<tl::enums::Reaction>, new: Vec<tl::enums::Reaction>) -> Vec<Reaction> {
+    let mut changes = BTreeMap::<String, i32>::new();
+    for emoji in old.into_iter().filter_map(emoji) {
+        *changes.entry(emoji).or_default() -= 1;
+    }
+    for emoji in new.into_iter().filter_map(emoji) {
+        *changes.entry(emoji).or_default() += 1;
+    }
+
+    changes
+        .into_iter()
+        .filter(|(_, change)| *change != 0)
+        .map(|(emoji, change)| Reaction::new(emoji, change))
+        .collect()
+}
+
+/// Reactions only arrive as raw updates, and bots only get them in
+/// groups where they are an administrator. Bots get the counts for
+/// anonymous reactions, e.g., in channels, but only the changes of
+/// single users otherwise.
+async fn handle_reactions(
+    groups: &GroupMap,
+    consumer: MemeSender,
+    update: tl::enums::Update,
+) -> Result<()> {
+    type Event = fn(Source, Vec<Reaction>, NaiveDateTime) -> MemeEvent;
+
+    let (peer, id, reactions, event): (_, _, _, Event) = match update {
+        tl::enums::Update::BotMessageReactions(update) => (
+            update.peer,
+            update.msg_id,
+            reactions(update.reactions),
+            MemeEvent::react,
+        ),
+        tl::enums::Update::BotMessageReaction(update) => (
+            update.peer,
+            update.msg_id,
+            reaction_changes(update.old_reactions, update.new_reactions),
+            MemeEvent::change_reactions,
+        ),
+        tl::enums::Update::MessageReactions(tl::types::UpdateMessageReactions {
+            peer,
+            msg_id,
+            reactions: tl::enums::MessageReactions::Reactions(reactions),
+            ..
+        }) => (
+            peer,
+            msg_id,
+            self::reactions(reactions.results),
+            MemeEvent::react,
+        ),
+        _ => return Ok(()),
+    };
+
+    let (chat, is_channel) = match peer {
+        tl::enums::Peer::Channel(channel) => (channel.channel_id, true),
+        tl::enums::Peer::Chat(chat) => (chat.chat_id, false),
+        tl::enums::Peer::User(_) => return Ok(()),
+    };
+    if !groups.contains_key(&chat) {
+        log::debug!("irrelevant reactions in chat {chat}");
+        return Ok(());
+    }
+
+    consumer
+        .send(event(
+            Source::telegram(None, None, Some(chat), is_channel, id),
+            reactions,
+            Utc::now().naive_utc(),
+        ))
+        .await
+}
+
+async fn process(
+    config: config::Telegram,
+    control: &mut broadcast::Receiver<Command>,
+    consumer: MemeSender,
+) -> Result<MemeSender> {
+    log::info!("starting telegram bot");
+
+    let client = connect(&config).await?;
+    save_session(&client, config.session_file())?;
+    let mut groups = groups(&config);
+    let mut known = known_groups(&groups);
+    let mut albums = Albums::default();
+
     loop {
         select! {
             update = client.next_update() => {
                 match update {
                     Ok(Update::NewMessage(message))  => {
-                        handle_message(&client, &mut groups, consumer.clone(), message, false).await?
+                        handle_message(&client, &config, &mut groups, &mut albums, consumer.clone(), message, false).await?
                     }
                     Ok(Update::MessageEdited(message)) => {
-                        handle_message(&client, &mut groups, consumer.clone(), message, true).await?
+                        handle_message(&client, &config, &mut groups, &mut albums, consumer.clone(), message, true).await?
                     }
                     Ok(Update::MessageDeleted(message)) => {
-                        handle_delete(consumer.clone(), message).await?
+                        handle_delete(&groups, consumer.clone(), message).await?
                     },
+                    Ok(Update::Raw(update)) => {
+                        handle_reactions(&groups, consumer.clone(), update).await?
+                    }
                     Err(err) => {
                         log::error!("error: {err:?}");
                     }
                     _ => {
                     }
                 }
+
+                save_session(&client, config.session_file())?;
+                let now_known = known_groups(&groups);
+                if now_known != known {
+                    save_groups(&now_known, config.session_file()).await?;
+                    known = now_known;
+                }
+            }
+
+            _ = albums.expired() => {
+                albums.send_expired(&consumer).await?;
             }
 
             Ok(command) = control.recv() => {
@@ -248,8 +650,164 @@ async fn process(
         }
     }
 
-    client.sign_out().await?;
+    albums.send_all(&consumer).await?;
+
+    if config.session_file().is_some() {
+        save_session(&client, config.session_file())?;
+    } else {
+        client.sign_out().await?;
+    }
     drop(client);
 
     Ok(consumer)
 }
+
+/// Number of messages requested at once during a backfill.
+const BACKFILL_BATCH_SIZE: i32 = 100;
+
+/// Feeds the history of a Telegram group into the consumer.
+///
+/// Bots are not allowed to iterate over the history of a chat, but
+/// they can request messages by id, so we walk the message ids in
+/// batches, starting after the last message processed in a
+/// previous run. Bots can't ask for the size of the history either,
+/// so the walk ends at the newest message the bot has seen in the
+/// group; everything after that arrives as regular updates.
+pub(crate) async fn backfill(
+    config: config::Telegram,
+    consumer: MemeSender,
+    mut progress: BackfillProgress,
+    group: i64,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+) -> Result<()> {
+    let mut groups = groups(&config);
+    let Some(known) = groups.get(&group) else {
+        bail!("group {group} is not configured");
+    };
+    let (Some(chat), Some(latest)) = (known.chat, known.last_message) else {
+        bail!(
+            "group {group} has not been seen yet, run the bot with a session file until a message arrives in the group"
+        );
+    };
+
+    let client = connect(&config).await?;
+    let mut albums = Albums::default();
+
+    let mut next = progress.last_message().map_or(1, |message| message + 1);
+    log::info!("backfilling group {group} from message {next} up to message {latest}");
+
+    while next <= latest {
+        let ids = (next..(next + BACKFILL_BATCH_SIZE).min(latest + 1)).collect::<Vec<_>>();
+        let messages = match client.get_messages_by_id(chat, &ids).await {
+            Ok(messages) => messages,
+            Err(err) => {
+                let err = Error::from(err);
+                match flood_wait(&err) {
+                    Some(delay) => {
+                        sleep(delay).await;
+                        continue;
+                    }
+                    None => return Err(err),
+                }
+            }
+        };
+        next += BACKFILL_BATCH_SIZE;
+
+        // message ids of basic groups are shared by all chats of the
+        // account, so skip over messages of other chats
+        for message in messages
+            .into_iter()
+            .flatten()
+            .filter(|message| message.chat().id() == group)
+        {
+            let id = message.id();
+            let date = message.date().date_naive();
+
+            if until.is_some_and(|until| date > until) {
+                log::info!("reached message {id} sent after {date}, stopping");
+                return albums.send_all(&consumer).await;
+            }
+
+            // history is ordered, so an album is complete as soon as
+            // we see a message that doesn't belong to it
+            if message
+                .grouped_id()
+                .is_none_or(|grouped_id| !albums.pending.contains_key(&grouped_id))
+            {
+                albums.send_all(&consumer).await?;
+            }
+
+            if since.is_none_or(|since| date >= since) && !progress.is_stored(id).await? {
+                handle_message(
+                    &client,
+                    &config,
+                    &mut groups,
+                    &mut albums,
+                    consumer.clone(),
+                    message,
+                    false,
+                )
+                .await?;
+            }
+        }
+
+        // missing messages up to the newest one we've seen have been
+        // deleted, but don't skip over albums that are still incomplete
+        let last = match albums.first_message() {
+            Some(first) => ids[ids.len() - 1].min(first - 1),
+            None => ids[ids.len() - 1],
+        };
+        progress.save(last).await?;
+        log::info!("backfilled group {group} up to message {last}");
+    }
+
+    albums.send_all(&consumer).await?;
+    log::info!("reached the newest message seen in group {group}, done");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use grammers_tl_types as tl;
+    use test_log::test;
+
+    use super::reaction_changes;
+    use crate::consumer::Reaction;
+
+    fn emoji(emoticon: &str) -> tl::enums::Reaction {
+        tl::enums::Reaction::Emoji(tl::types::ReactionEmoji {
+            emoticon: emoticon.to_string(),
+        })
+    }
+
+    #[test]
+    fn translates_reaction_changes() {
+        let custom =
+            || tl::enums::Reaction::CustomEmoji(tl::types::ReactionCustomEmoji { document_id: 42 });
+
+        assert_eq!(
+            reaction_changes(vec![], vec![emoji("👍"), custom()]),
+            vec![
+                Reaction::new("custom:42".to_string(), 1),
+                Reaction::new("👍".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            reaction_changes(
+                vec![emoji("👍"), emoji("😂")],
+                vec![emoji("😂"), emoji("🔥")]
+            ),
+            vec![
+                Reaction::new("👍".to_string(), -1),
+                Reaction::new("🔥".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            reaction_changes(vec![emoji("👍")], vec![tl::enums::Reaction::Empty]),
+            vec![Reaction::new("👍".to_string(), -1)]
+        );
+        assert!(reaction_changes(vec![emoji("👍")], vec![emoji("👍")]).is_empty());
+    }
+}
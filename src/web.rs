@@ -0,0 +1,503 @@
+// © 2025 Maximilian Marx
+// SPDX-FileContributor: Maximilian Marx
+//
+// SPDX-License-Identifier: EUPL-1.2
+
+use std::{fmt::Write, ops::RangeInclusive};
+
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+};
+use chrono::{Days, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use crate::{
+    config::{
+        DatabaseConfiguration, HttpConfiguration, ProcessingConfiguration, StorageConfiguration,
+    },
+    consumer::{self, Archive, Entry, RankedEntry, TagCount},
+};
+
+/// How many memes a page holds, unless asked for otherwise.
+const PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+/// How many days the best memes are picked from, unless asked for
+/// otherwise.
+const BEST_OF_DAYS: u64 = 7;
+/// How many of the most used tags the gallery offers as filters.
+const GALLERY_TAGS: usize = 20;
+
+const STYLE: &str = "
+body { font-family: sans-serif; max-width: 50em; margin: auto; padding: 1em; }
+form { display: flex; gap: 0.5em; margin-bottom: 1em; }
+form input { flex: 1; }
+.tags { flex-wrap: wrap; gap: 0.5em; justify-content: flex-start; padding: 0 0 1em; }
+article { border-bottom: 1px solid #ccc; padding: 1em 0; }
+article img, article video { max-width: 100%; display: block; margin: 0.5em 0; }
+article p { white-space: pre-wrap; }
+footer { color: #666; font-size: 0.9em; }
+nav { display: flex; justify-content: space-between; padding: 1em 0; }
+.media { overflow: hidden; }
+.spoiler { cursor: pointer; }
+.spoiler > * { filter: blur(2em); pointer-events: none; }
+";
+
+/// Serves the archive: a gallery at `/`, a JSON API below `/api`,
+/// and the stored files below `/files`. Never changes anything.
+#[derive(Debug)]
+pub struct Web {
+    task: JoinHandle<Result<()>>,
+    control: Sender<Command>,
+}
+
+impl Web {
+    pub(crate) fn new(
+        config: HttpConfiguration,
+        storage: StorageConfiguration,
+        processing: ProcessingConfiguration,
+        database: DatabaseConfiguration,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(8);
+        let task = tokio::spawn(async move {
+            let result = process(config, storage, processing, database, rx).await;
+            if let Err(ref err) = result {
+                log::error!("{err}");
+            }
+            result
+        });
+
+        Ok(Self { task, control: tx })
+    }
+
+    pub(crate) async fn shutdown(self) -> Result<()> {
+        log::info!("shutting down web server");
+        if !self.control.is_closed() {
+            self.control.send(Command::Shutdown).await?;
+        }
+        self.task.await??;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Shutdown,
+}
+
+async fn process(
+    config: HttpConfiguration,
+    storage: StorageConfiguration,
+    processing: ProcessingConfiguration,
+    database: DatabaseConfiguration,
+    mut control: Receiver<Command>,
+) -> Result<()> {
+    log::info!("starting web server");
+
+    let archive = Archive::open(&storage, &processing, &database)?;
+    let listener = TcpListener::bind(config.bind())
+        .await
+        .with_context(|| format!("failed to listen on {}", config.bind()))?;
+    log::info!("serving the archive at http://{}", config.bind());
+
+    let app = Router::new()
+        .route("/", get(gallery))
+        .route("/api/memes", get(list))
+        .route("/api/memes/{id}", get(meme))
+        .route("/api/memes/{id}/variants", get(variants))
+        .route("/api/best", get(best))
+        .route("/api/tags", get(tags))
+        .route("/files/{*file}", get(file))
+        .with_state(archive);
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            control.recv().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Errors are logged, but not shown to clients.
+struct Error(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for Error {
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        log::error!("failed to serve request: {}", self.0);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// Filters and paging, for both the gallery and the API.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Params {
+    /// words to search for, see [`consumer::Query::text`]
+    q: Option<String>,
+    channel: Option<String>,
+    account: Option<String>,
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    tag: Option<String>,
+    spoiler: Option<bool>,
+    /// counting from 1
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+impl Params {
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+
+    fn query(&self) -> consumer::Query {
+        consumer::Query {
+            text: self.q.clone().unwrap_or_default(),
+            channel: self.channel.clone(),
+            account: self.account.clone(),
+            since: self.since,
+            until: self.until,
+            spoiler: self.spoiler,
+            tag: self.tag.clone(),
+            // one more, to know whether there is another page
+            limit: self.per_page() + 1,
+            offset: (self.page() - 1).saturating_mul(self.per_page()),
+        }
+    }
+
+    /// The query string for page `page` of the same memes.
+    fn link(&self, page: i64) -> Result<String> {
+        Ok(format!(
+            "?{}",
+            serde_urlencoded::to_string(Self {
+                page: Some(page),
+                ..self.clone()
+            })?
+        ))
+    }
+}
+
+/// A page of memes, newest or best matches first.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Page {
+    memes: Vec<Entry>,
+    page: i64,
+    per_page: i64,
+    /// whether there is another page
+    more: bool,
+}
+
+impl Page {
+    async fn load(archive: &Archive, params: &Params) -> Result<Self> {
+        let mut memes = archive.memes(params.query()).await?;
+        let more = memes.len() as i64 > params.per_page();
+        memes.truncate(params.per_page() as usize);
+
+        Ok(Self {
+            memes,
+            page: params.page(),
+            per_page: params.per_page(),
+            more,
+        })
+    }
+}
+
+async fn list(
+    State(archive): State<Archive>,
+    Query(params): Query<Params>,
+) -> Result<Json<Page>, Error> {
+    Ok(Json(Page::load(&archive, &params).await?))
+}
+
+async fn meme(State(archive): State<Archive>, Path(id): Path<i32>) -> Result<Response, Error> {
+    Ok(match archive.meme(id).await? {
+        Some(entry) => Json(entry).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// The original of a meme and all of its reposts.
+async fn variants(State(archive): State<Archive>, Path(id): Path<i32>) -> Result<Response, Error> {
+    let variants = archive.variants(id).await?;
+    Ok(if variants.is_empty() {
+        StatusCode::NOT_FOUND.into_response()
+    } else {
+        Json(variants).into_response()
+    })
+}
+
+/// The period and number of the best memes, by their reactions.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BestParams {
+    /// defaults to a week before `until`
+    since: Option<NaiveDate>,
+    /// inclusive, defaults to today
+    until: Option<NaiveDate>,
+    limit: Option<i64>,
+}
+
+impl BestParams {
+    fn period(&self, today: NaiveDate) -> RangeInclusive<NaiveDateTime> {
+        let until = self.until.unwrap_or(today);
+        let since = self
+            .since
+            .or_else(|| until.checked_sub_days(Days::new(BEST_OF_DAYS - 1)))
+            .unwrap_or(NaiveDate::MIN);
+
+        since.and_time(Default::default())
+            ..=until
+                .and_hms_micro_opt(23, 59, 59, 999_999)
+                .expect("is a valid time")
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit.unwrap_or(PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+}
+
+async fn best(
+    State(archive): State<Archive>,
+    Query(params): Query<BestParams>,
+) -> Result<Json<Vec<RankedEntry>>, Error> {
+    let period = params.period(Utc::now().date_naive());
+
+    Ok(Json(archive.best_of(period, params.limit()).await?))
+}
+
+async fn tags(State(archive): State<Archive>) -> Result<Json<Vec<TagCount>>, Error> {
+    Ok(Json(archive.tags().await?))
+}
+
+async fn file(State(archive): State<Archive>, Path(file): Path<String>) -> Result<Response, Error> {
+    Ok(match archive.file(&file).await? {
+        // files are named after their contents, so they never change
+        Some(data) => (
+            [
+                (header::CONTENT_TYPE, consumer::mime_type(&file)),
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            data,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Escape `text` for use in HTML, including attribute values.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+async fn gallery(
+    State(archive): State<Archive>,
+    Query(params): Query<Params>,
+) -> Result<Html<String>, Error> {
+    let page = Page::load(&archive, &params).await?;
+    let tags = archive.tags().await?;
+    let mut html = String::new();
+
+    writeln!(
+        html,
+        r#"<!DOCTYPE html>
+<html lang="de">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>kommemeorate</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<form method="get" action="/">
+<input type="search" name="q" value="{}" placeholder="Search captions and images">
+<button>Search</button>
+</form>"#,
+        escape(params.q.as_deref().unwrap_or_default())
+    )?;
+
+    if !tags.is_empty() {
+        writeln!(html, r#"<nav class="tags">"#)?;
+        for tag in tags.iter().take(GALLERY_TAGS) {
+            let link = Params {
+                tag: Some(tag.name.clone()),
+                ..Default::default()
+            }
+            .link(1)?;
+            writeln!(
+                html,
+                r#"<span><a href="{}">#{}</a> <small>{}</small></span>"#,
+                escape(&link),
+                escape(&tag.name),
+                tag.count
+            )?;
+        }
+        writeln!(html, "</nav>")?;
+    }
+    writeln!(html, "<main>")?;
+
+    for entry in &page.memes {
+        writeln!(html, "<article>")?;
+
+        // spoilers stay blurred until clicked
+        if entry.spoiler {
+            writeln!(
+                html,
+                r#"<div class="media spoiler" title="Spoiler" onclick="this.classList.remove('spoiler')">"#
+            )?;
+        } else {
+            writeln!(html, r#"<div class="media">"#)?;
+        }
+        for image in &entry.images {
+            let src = format!("/files/{}", escape(&image.file));
+            if let Some(thumbnail) = image.thumbnails.first() {
+                // fall back to the original until the thumbnail exists
+                writeln!(
+                    html,
+                    r#"<a href="{src}"><img src="/files/{}" alt="" loading="lazy" onerror="this.onerror=null; this.src='{src}'"></a>"#,
+                    escape(thumbnail)
+                )?;
+            } else if image.mime_type.starts_with("image/") {
+                writeln!(html, r#"<img src="{src}" alt="" loading="lazy">"#)?;
+            } else if image.mime_type.starts_with("video/") {
+                writeln!(
+                    html,
+                    r#"<video src="{src}" controls loop muted preload="metadata"></video>"#
+                )?;
+            } else {
+                writeln!(html, r#"<a href="{src}">{src}</a>"#)?;
+            }
+        }
+        writeln!(html, "</div>")?;
+
+        if !entry.text.is_empty() {
+            writeln!(html, "<p>{}</p>", escape(&entry.text))?;
+        }
+
+        write!(
+            html,
+            r#"<footer><a href="/api/memes/{}">{}</a> in {} by {}"#,
+            entry.id,
+            entry.timestamp.format("%Y-%m-%d %H:%M"),
+            escape(&entry.channel),
+            escape(&entry.account)
+        )?;
+        for tag in &entry.tags {
+            let link = Params {
+                tag: Some(tag.clone()),
+                ..Default::default()
+            }
+            .link(1)?;
+            write!(html, r#" <a href="{}">#{}</a>"#, escape(&link), escape(tag))?;
+        }
+        for reaction in &entry.reactions {
+            write!(html, " {} {}", escape(&reaction.emoji), reaction.count)?;
+        }
+        writeln!(html, "</footer>\n</article>")?;
+    }
+
+    if page.memes.is_empty() {
+        writeln!(html, "<p>No memes found.</p>")?;
+    }
+
+    writeln!(html, "</main>\n<nav>")?;
+    if page.page > 1 {
+        let link = params.link(page.page - 1)?;
+        writeln!(html, r#"<a href="{}">← newer</a>"#, escape(&link))?;
+    }
+    writeln!(html, "<span></span>")?;
+    if page.more {
+        let link = params.link(page.page + 1)?;
+        writeln!(html, r#"<a href="{}">older →</a>"#, escape(&link))?;
+    }
+    writeln!(html, "</nav>\n</body>\n</html>")?;
+
+    Ok(Html(html))
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use chrono::NaiveDate;
+
+    use super::{BestParams, Params, escape};
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn links_keep_filters() {
+        let params = Params {
+            q: Some("drucker brennt".to_string()),
+            tag: Some("koma".to_string()),
+            page: Some(2),
+            per_page: Some(10),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            params.link(3).expect("can serialise"),
+            "?q=drucker+brennt&tag=koma&page=3&perPage=10"
+        );
+    }
+
+    #[test]
+    fn pages_are_bounded() {
+        let params = Params {
+            page: Some(0),
+            per_page: Some(1_000_000),
+            ..Default::default()
+        };
+        let query = params.query();
+
+        assert_eq!(query.offset, 0);
+        assert_eq!(query.limit, super::MAX_PER_PAGE + 1);
+    }
+
+    #[test]
+    fn best_of_defaults_to_the_last_week() {
+        let date = |day| NaiveDate::from_ymd_opt(2025, 6, day).expect("is a valid date");
+        let period = BestParams::default().period(date(16));
+
+        assert_eq!(period.start().date(), date(10));
+        assert_eq!(period.end().date(), date(16));
+        assert_eq!(
+            BestParams {
+                limit: Some(0),
+                ..Default::default()
+            }
+            .limit(),
+            1
+        );
+    }
+}